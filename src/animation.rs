@@ -0,0 +1,157 @@
+//! Hashing several frames of animated GIFs and WebPs, for
+//! `--animated-signature`.
+
+use std::io::{BufRead, Seek};
+
+#[cfg(feature = "gif")]
+use image::codecs::gif::GifDecoder;
+#[cfg(feature = "webp")]
+use image::codecs::webp::WebPDecoder;
+#[cfg(any(feature = "gif", feature = "webp"))]
+use image::AnimationDecoder;
+use image::{DynamicImage, ImageFormat};
+
+use crate::{hamming_distance, hash_image, Args};
+
+/// How many frames are sampled from each animation.
+pub const SIGNATURE_FRAMES: usize = 8;
+
+/// Frames are shrunk to fit in this size as they're decoded, so long
+/// animations don't need every frame in memory at full size. It's still much
+/// bigger than what the hash is computed from.
+#[cfg(any(feature = "gif", feature = "webp"))]
+const FRAME_SIZE: u32 = 64;
+
+/// Decode every frame of an animated GIF or WebP and pick `SIGNATURE_FRAMES` of
+/// them spread evenly across it, always starting with the first one. Returns
+/// `None` for images that aren't animated or whose frames can't be decoded, so
+/// they're only hashed by their first frame.
+#[cfg(any(feature = "gif", feature = "webp"))]
+pub fn sampled_frames(
+    reader: impl BufRead + Seek,
+    format: ImageFormat,
+) -> Option<Vec<DynamicImage>> {
+    let frames = match format {
+        #[cfg(feature = "gif")]
+        ImageFormat::Gif => GifDecoder::new(reader).ok()?.into_frames(),
+        #[cfg(feature = "webp")]
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(reader).ok()?;
+            if !decoder.has_animation() {
+                return None;
+            }
+            decoder.into_frames()
+        }
+        _ => return None,
+    };
+    let frames = frames
+        .map(|frame| {
+            frame.map(|frame| {
+                DynamicImage::ImageRgba8(frame.into_buffer()).thumbnail(FRAME_SIZE, FRAME_SIZE)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    if frames.len() < 2 {
+        return None;
+    }
+    Some(
+        (0..SIGNATURE_FRAMES)
+            .map(|i| frames[i * frames.len() / SIGNATURE_FRAMES].clone())
+            .collect(),
+    )
+}
+
+/// Without the decoders for animated formats, every image is hashed by its
+/// first frame.
+#[cfg(not(any(feature = "gif", feature = "webp")))]
+pub fn sampled_frames(
+    _reader: impl BufRead + Seek,
+    _format: ImageFormat,
+) -> Option<Vec<DynamicImage>> {
+    None
+}
+
+/// Hash each of the sampled frames and put the hashes one after another, or
+/// return `None` if there aren't any frames.
+pub fn signature(
+    hasher: &image_hasher::Hasher,
+    frames: Vec<DynamicImage>,
+    args: &Args,
+) -> Option<Vec<u8>> {
+    if frames.is_empty() {
+        return None;
+    }
+    Some(
+        frames
+            .into_iter()
+            .flat_map(|frame| hash_image(hasher, frame, args))
+            .collect(),
+    )
+}
+
+/// How different two signatures are, as the average number of bits that differ
+/// between their frames rounded up, so it's on the same scale as the distance
+/// between two single hashes. Each frame is compared with the closest of the
+/// other animation's frames around the same point, so an animation that was
+/// re-encoded with a frame dropped or doubled still lines up.
+pub fn sequence_distance(a: &[u8], b: &[u8]) -> u32 {
+    if a.len() != b.len() || a.len() < SIGNATURE_FRAMES {
+        return u32::MAX;
+    }
+    let frame_len = a.len() / SIGNATURE_FRAMES;
+    let one_way = |a: &[u8], b: &[u8]| {
+        let b_frames = b.chunks(frame_len).collect::<Vec<_>>();
+        let total_bits = a
+            .chunks(frame_len)
+            .enumerate()
+            .map(|(i, a_frame)| {
+                let nearby = &b_frames[i.saturating_sub(1)..(i + 2).min(b_frames.len())];
+                nearby
+                    .iter()
+                    .map(|b_frame| hamming_distance(a_frame, b_frame))
+                    .min()
+                    .unwrap()
+            })
+            .sum::<u32>();
+        total_bits.div_ceil(SIGNATURE_FRAMES as u32)
+    };
+    one_way(a, b).max(one_way(b, a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A signature with one byte per frame.
+    fn signature_of(frames: [u8; SIGNATURE_FRAMES]) -> Vec<u8> {
+        frames.to_vec()
+    }
+
+    #[test]
+    fn sequence_distance_averages_frames() {
+        let a = signature_of([0x00, 0x0f, 0xf0, 0xff, 0x00, 0x0f, 0xf0, 0xff]);
+        assert_eq!(sequence_distance(&a, &a), 0);
+
+        // 1 bit in every frame
+        let b = signature_of([0x01, 0x0e, 0xf1, 0xfe, 0x01, 0x0e, 0xf1, 0xfe]);
+        assert_eq!(sequence_distance(&a, &b), 1);
+
+        // 4 bits in one frame averages out to 1
+        let c = signature_of([0x00, 0x0f, 0xf0, 0xff, 0x3c, 0x0f, 0xf0, 0xff]);
+        assert_eq!(sequence_distance(&a, &c), 1);
+
+        assert_eq!(sequence_distance(&a, &a[..4]), u32::MAX);
+    }
+
+    #[test]
+    fn sequence_distance_tolerates_shifted_frames() {
+        let a = signature_of([0x00, 0x00, 0x0f, 0x0f, 0xf0, 0xf0, 0xff, 0xff]);
+        // the same frames, but changing one sample earlier
+        let shifted = signature_of([0x00, 0x0f, 0x0f, 0xf0, 0xf0, 0xff, 0xff, 0xff]);
+        assert_eq!(sequence_distance(&a, &shifted), 0);
+
+        let reversed = signature_of([0xff, 0xff, 0xf0, 0xf0, 0x0f, 0x0f, 0x00, 0x00]);
+        assert!(sequence_distance(&a, &reversed) > 1);
+    }
+}
@@ -0,0 +1,90 @@
+//! Reading images out of a zip archive without extracting it, for `--archive`.
+
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use parking_lot::Mutex;
+use zip::{result::ZipError, ZipArchive};
+
+use crate::{error::ImdedupError, MAX_LOADED_SIZE};
+
+pub struct Archive {
+    pub path: PathBuf,
+    // entries are only read one at a time, but decoding them can happen in
+    // parallel once they're in memory
+    zip: Mutex<ZipArchive<fs::File>>,
+    /// The entries are treated as being as old as the archive.
+    pub created_at: SystemTime,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Self, ImdedupError> {
+        let file = fs::File::open(path).map_err(|err| ImdedupError::io(path, err))?;
+        let created_at = file
+            .metadata()
+            .and_then(|metadata| metadata.created())
+            .map_err(|err| ImdedupError::io(path, err))?;
+        let zip = ZipArchive::new(file).map_err(|err| archive_error(path, err))?;
+        Ok(Self {
+            path: path.to_owned(),
+            zip: Mutex::new(zip),
+            created_at,
+        })
+    }
+
+    /// The names of every file in the archive, not including directories.
+    pub fn file_names(&self) -> Result<Vec<String>, ImdedupError> {
+        let mut zip = self.zip.lock();
+        let mut names = Vec::new();
+        for i in 0..zip.len() {
+            let entry = zip.by_index(i).map_err(|err| self.error(err))?;
+            if entry.is_file() {
+                names.push(entry.name().map_err(|err| self.error(err))?.into_owned());
+            }
+        }
+        Ok(names)
+    }
+
+    /// Read an entry's decompressed contents.
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, ImdedupError> {
+        let mut zip = self.zip.lock();
+        let entry = zip.by_name(name).map_err(|err| self.error(err))?;
+        // the size in the header can't be trusted, so it's only known how big
+        // an entry is by decompressing it
+        let mut bytes = Vec::new();
+        entry
+            .take(MAX_LOADED_SIZE + 1)
+            .read_to_end(&mut bytes)
+            .map_err(|err| ImdedupError::io(self.entry_path(name), err))?;
+        if bytes.len() as u64 > MAX_LOADED_SIZE {
+            return Err(ImdedupError::Archive {
+                path: self.path.clone(),
+                message: format!(
+                    "{name} is larger than {} MiB once decompressed",
+                    MAX_LOADED_SIZE / 1024 / 1024
+                ),
+            });
+        }
+        Ok(bytes)
+    }
+
+    /// How an entry is identified in the output, like `archive.zip:dir/a.jpg`.
+    pub fn entry_path(&self, name: &str) -> String {
+        format!("{}:{name}", self.path.display())
+    }
+
+    fn error(&self, err: ZipError) -> ImdedupError {
+        archive_error(&self.path, err)
+    }
+}
+
+fn archive_error(path: &Path, err: ZipError) -> ImdedupError {
+    ImdedupError::Archive {
+        path: path.to_owned(),
+        message: err.to_string(),
+    }
+}
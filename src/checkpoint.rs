@@ -0,0 +1,289 @@
+//! Saving and restoring the state of an interrupted scan.
+//!
+//! The checkpoint is a text file with a header line, the options that affect
+//! hashing, a line with the counts so far, and then a line for every processed
+//! file and stored image:
+//!
+//! ```text
+//! imdedup checkpoint 3
+//! settings <hash settings>
+//! counts <processed> <dup> <sim> <uniq> <blk> <err>
+//! processed <path>
+//! image <hash> <file size> <created secs> <created nanos> <width> <height> <is url> <is reference> <matched> <signature> <path>
+//! ```
+//!
+//! The signature is the hex-encoded `--animated-signature` of an animation, or
+//! `-` if there isn't one.
+//!
+//! Paths are always last so they can contain spaces, and backslashes and
+//! newlines in them are escaped.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{error::ImdedupError, store_image, stored_images, StoredImage, StoredImages};
+
+const HEADER: &str = "imdedup checkpoint 3";
+
+#[derive(Default, Clone, Copy)]
+pub struct Counts {
+    pub processed: u64,
+    pub dup: u64,
+    pub sim: u64,
+    pub uniq: u64,
+    pub blk: u64,
+    pub err: u64,
+}
+
+pub struct Checkpoint {
+    pub counts: Counts,
+    pub processed: HashSet<String>,
+    pub hashes: StoredImages,
+}
+
+fn escape_path(path: &str) -> String {
+    path.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape_path(escaped: &str) -> String {
+    let mut path = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => path.push('\n'),
+                Some(c) => path.push(c),
+                None => path.push('\\'),
+            }
+        } else {
+            path.push(c);
+        }
+    }
+    path
+}
+
+/// Write the checkpoint to a temporary file next to `path` and then rename it
+/// over `path`, so a crash while writing never leaves a corrupted checkpoint.
+pub fn save(
+    path: &Path,
+    settings: &str,
+    counts: Counts,
+    processed: &HashSet<String>,
+    hashes: &StoredImages,
+) -> Result<(), ImdedupError> {
+    let mut contents = format!("{HEADER}\nsettings {settings}\n");
+    let Counts {
+        processed: processed_count,
+        dup,
+        sim,
+        uniq,
+        blk,
+        err,
+    } = counts;
+    contents.push_str(&format!(
+        "counts {processed_count} {dup} {sim} {uniq} {blk} {err}\n"
+    ));
+    for processed_path in processed {
+        contents.push_str(&format!("processed {}\n", escape_path(processed_path)));
+    }
+    for (hash, image) in stored_images(hashes) {
+        let created_at = image
+            .created_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        contents.push_str(&format!(
+            "image {} {} {} {} {} {} {} {} {} {} {}\n",
+            hex::encode(hash),
+            image.file_size,
+            created_at.as_secs(),
+            created_at.subsec_nanos(),
+            image.dimensions.0,
+            image.dimensions.1,
+            image.is_url as u8,
+            image.is_reference as u8,
+            image.matched as u8,
+            image
+                .signature
+                .as_ref()
+                .map_or("-".to_string(), hex::encode),
+            escape_path(&image.path)
+        ));
+    }
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, contents).map_err(|err| ImdedupError::io(&temp_path, err))?;
+    fs::rename(&temp_path, path).map_err(|err| ImdedupError::io(path, err))
+}
+
+/// Read a checkpoint, or return `None` if there isn't one yet. Checkpoints saved
+/// with different hash settings are rejected, since their hashes can't be
+/// compared with new ones.
+pub fn load(path: &Path, settings: &str) -> Result<Option<Checkpoint>, ImdedupError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(ImdedupError::io(path, err)),
+    };
+    let invalid = |line: usize| {
+        ImdedupError::InvalidArgument(format!(
+            "invalid checkpoint {} on line {}",
+            path.display(),
+            line + 1
+        ))
+    };
+
+    let mut lines = contents.lines().enumerate();
+    if lines.next().map(|(_, line)| line) != Some(HEADER) {
+        return Err(invalid(0));
+    }
+    let saved_settings = lines
+        .next()
+        .and_then(|(_, line)| line.strip_prefix("settings "))
+        .ok_or_else(|| invalid(1))?;
+    if saved_settings != settings {
+        return Err(ImdedupError::InvalidArgument(format!(
+            "checkpoint {} was saved with different hashing options ({saved_settings}) than the current ones ({settings}), so its hashes can't be reused. run with the same options, or delete it to start over",
+            path.display()
+        )));
+    }
+
+    let mut checkpoint = Checkpoint {
+        counts: Counts::default(),
+        processed: HashSet::new(),
+        hashes: StoredImages::new(),
+    };
+    for (i, line) in lines {
+        let (kind, rest) = line.split_once(' ').ok_or_else(|| invalid(i))?;
+        match kind {
+            "counts" => {
+                let counts = rest
+                    .split(' ')
+                    .map(|count| count.parse::<u64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid(i))?;
+                let [processed, dup, sim, uniq, blk, err] = counts[..] else {
+                    return Err(invalid(i));
+                };
+                checkpoint.counts = Counts {
+                    processed,
+                    dup,
+                    sim,
+                    uniq,
+                    blk,
+                    err,
+                };
+            }
+            "processed" => {
+                checkpoint.processed.insert(unescape_path(rest));
+            }
+            "image" => {
+                let fields = rest.splitn(11, ' ').collect::<Vec<_>>();
+                let [hash, file_size, secs, nanos, width, height, is_url, is_reference, matched, signature, image_path] =
+                    fields[..]
+                else {
+                    return Err(invalid(i));
+                };
+                let number = |field: &str| field.parse::<u64>().map_err(|_| invalid(i));
+                let created_at =
+                    SystemTime::UNIX_EPOCH + Duration::new(number(secs)?, number(nanos)? as u32);
+                let image = StoredImage {
+                    path: unescape_path(image_path),
+                    file_size: number(file_size)?,
+                    created_at,
+                    dimensions: (number(width)? as u32, number(height)? as u32),
+                    is_url: is_url == "1",
+                    is_reference: is_reference == "1",
+                    matched: matched == "1",
+                    signature: match signature {
+                        "-" => None,
+                        signature => Some(hex::decode(signature).map_err(|_| invalid(i))?),
+                    },
+                };
+                let hash = hex::decode(hash).map_err(|_| invalid(i))?;
+                store_image(&mut checkpoint.hashes, hash, image);
+            }
+            _ => return Err(invalid(i)),
+        }
+    }
+
+    Ok(Some(checkpoint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+
+        let mut processed = HashSet::new();
+        processed.insert("a b\\c\nd.png".to_string());
+        let mut hashes = StoredImages::new();
+        hashes.insert(
+            vec![0x12, 0x34],
+            vec![StoredImage {
+                path: "some dir/a b.png".to_string(),
+                file_size: 1234,
+                created_at: SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 5),
+                dimensions: (640, 480),
+                is_url: false,
+                is_reference: true,
+                matched: true,
+                signature: Some(vec![0xab; 16]),
+            }],
+        );
+        let counts = Counts {
+            processed: 2,
+            dup: 1,
+            uniq: 1,
+            ..Counts::default()
+        };
+
+        save(&path, "dct=0", counts, &processed, &hashes).unwrap();
+        let checkpoint = load(&path, "dct=0").unwrap().unwrap();
+
+        assert_eq!(checkpoint.processed, processed);
+        assert_eq!(checkpoint.counts.processed, 2);
+        assert_eq!(checkpoint.counts.dup, 1);
+        assert_eq!(checkpoint.counts.uniq, 1);
+        let image = &checkpoint.hashes[&vec![0x12, 0x34]][0];
+        assert_eq!(image.path, "some dir/a b.png");
+        assert_eq!(image.file_size, 1234);
+        assert_eq!(image.created_at, hashes[&vec![0x12, 0x34]][0].created_at);
+        assert_eq!(image.dimensions, (640, 480));
+        assert!(!image.is_url);
+        assert!(image.is_reference);
+        assert!(image.matched);
+        assert_eq!(image.signature, Some(vec![0xab; 16]));
+    }
+
+    #[test]
+    fn missing_checkpoint_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load(&dir.path().join("missing"), "dct=0")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_different_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+        save(
+            &path,
+            "dct=0",
+            Counts::default(),
+            &HashSet::new(),
+            &StoredImages::new(),
+        )
+        .unwrap();
+        assert!(load(&path, "dct=1").is_err());
+    }
+}
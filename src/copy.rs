@@ -0,0 +1,171 @@
+//! Copying one image from each group of matching images into a directory, for
+//! `--copy-uniques-to`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::ImdedupError, keep_rank, match_distance, stored_images, KeepPolicy, StoredImage,
+    StoredImages, SIMILARITY_THRESHOLD,
+};
+
+/// Pick one image from every group of matching images, preferring the one the
+/// keep policy would keep. Groups containing an image older than `--since`
+/// already have a copy elsewhere, so nothing is picked from them. Images are
+/// matched the same way as during the scan.
+pub fn representatives<'a>(
+    hashes: &'a StoredImages,
+    keep: KeepPolicy,
+    weights: Option<&[f64]>,
+) -> Vec<&'a StoredImage> {
+    let mut images = stored_images(hashes).collect::<Vec<_>>();
+    images.sort_by(|(_, a), (_, b)| {
+        b.is_reference
+            .cmp(&a.is_reference)
+            .then_with(|| keep_rank(b, keep).cmp(&keep_rank(a, keep)))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    let mut picked_images = Vec::<(&Vec<u8>, &StoredImage)>::new();
+    let mut picked = Vec::new();
+    for (hash, image) in images {
+        let already_picked = picked_images.iter().any(|(picked_hash, picked_image)| {
+            match_distance(
+                hash,
+                image.signature.as_deref(),
+                picked_hash,
+                picked_image.signature.as_deref(),
+                weights,
+            ) <= SIMILARITY_THRESHOLD
+        });
+        if already_picked {
+            continue;
+        }
+        picked_images.push((hash, image));
+        if !image.is_reference {
+            picked.push(image);
+        }
+    }
+    picked
+}
+
+/// Find a path in the directory for the file name that isn't taken yet, adding
+/// a ` (n)` suffix if needed.
+fn free_destination(dir: &Path, source: &Path) -> PathBuf {
+    let file_name = source.file_name().unwrap_or_default();
+    let destination = dir.join(file_name);
+    if !destination.exists() {
+        return destination;
+    }
+
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = source
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| dir.join(format!("{stem} ({n}){extension}")))
+        .find(|destination| !destination.exists())
+        .unwrap()
+}
+
+/// Copy the images into the directory, keeping their modification and access
+/// times. Images from urls are skipped. Returns the number of images copied.
+pub fn copy_to(dir: &Path, images: &[&StoredImage]) -> Result<usize, ImdedupError> {
+    fs::create_dir_all(dir).map_err(|err| ImdedupError::io(dir, err))?;
+
+    let mut copied = 0;
+    for image in images {
+        if image.is_url {
+            continue;
+        }
+        let source = Path::new(&image.path);
+        let destination = free_destination(dir, source);
+        fs::copy(source, &destination).map_err(|err| ImdedupError::io(source, err))?;
+
+        let metadata = fs::metadata(source).map_err(|err| ImdedupError::io(source, err))?;
+        let mut times = fs::FileTimes::new();
+        if let Ok(modified) = metadata.modified() {
+            times = times.set_modified(modified);
+        }
+        if let Ok(accessed) = metadata.accessed() {
+            times = times.set_accessed(accessed);
+        }
+        fs::File::options()
+            .write(true)
+            .open(&destination)
+            .and_then(|file| file.set_times(times))
+            .map_err(|err| ImdedupError::io(&destination, err))?;
+
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn stored_image(path: &str, file_size: u64) -> StoredImage {
+        StoredImage {
+            path: path.to_string(),
+            file_size,
+            created_at: SystemTime::UNIX_EPOCH,
+            dimensions: (0, 0),
+            is_url: false,
+            is_reference: false,
+            matched: false,
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn picks_one_image_per_group() {
+        let mut hashes = StoredImages::new();
+        hashes.insert(vec![0b0000_0000], vec![stored_image("small", 100)]);
+        hashes.insert(vec![0b0000_0001], vec![stored_image("large", 200)]);
+        hashes.insert(vec![0b1111_1111], vec![stored_image("unrelated", 100)]);
+
+        let mut picked = representatives(&hashes, KeepPolicy::Size, None)
+            .iter()
+            .map(|image| image.path.as_str())
+            .collect::<Vec<_>>();
+        picked.sort();
+        assert_eq!(picked, ["large", "unrelated"]);
+    }
+
+    #[test]
+    fn compares_animations_by_their_frames() {
+        let animation = |path: &str, frames: u8| StoredImage {
+            signature: Some(vec![frames; 8]),
+            ..stored_image(path, 100)
+        };
+        // the same first frame, but nothing else in common
+        let hashes = StoredImages::from([(
+            vec![0b0000_0000],
+            vec![animation("a", 0x00), animation("b", 0xff)],
+        )]);
+        assert_eq!(representatives(&hashes, KeepPolicy::Size, None).len(), 2);
+    }
+
+    #[test]
+    fn suffixes_taken_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = Path::new("somewhere/cat.png");
+        assert_eq!(
+            free_destination(dir.path(), source),
+            dir.path().join("cat.png")
+        );
+
+        fs::write(dir.path().join("cat.png"), "").unwrap();
+        fs::write(dir.path().join("cat (1).png"), "").unwrap();
+        assert_eq!(
+            free_destination(dir.path(), source),
+            dir.path().join("cat (2).png")
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Finding whole directories whose images all match another directory's, for
+//! `--duplicate-dirs`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{match_distance, SIMILARITY_THRESHOLD};
+
+/// A scanned file's path, hash, and `--animated-signature`.
+pub type ScannedFile = (String, Vec<u8>, Option<Vec<u8>>);
+
+/// Put every image in a numbered group with the images it matches, directly or
+/// through other images. They're matched the same way as during the scan.
+fn group_ids(images: &[ScannedFile], weights: Option<&[f64]>) -> Vec<usize> {
+    let mut parents = (0..images.len()).collect::<Vec<_>>();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    // every pair is compared, which is quadratic in the number of images, but
+    // so is the scan, which compares each image against every stored one
+    for (i, (_, a_hash, a_signature)) in images.iter().enumerate() {
+        for (j, (_, b_hash, b_signature)) in images.iter().enumerate().skip(i + 1) {
+            let distance = match_distance(
+                a_hash,
+                a_signature.as_deref(),
+                b_hash,
+                b_signature.as_deref(),
+                weights,
+            );
+            if distance <= SIMILARITY_THRESHOLD {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+    (0..images.len()).map(|i| root(&mut parents, i)).collect()
+}
+
+/// Find sets of directories under `root` that contain matching images, with
+/// nothing left over in any of them. Subdirectories count towards every
+/// directory above them, and only the topmost directories of duplicate trees
+/// are returned, so mirrored trees are reported once rather than once for
+/// every level. Each set is sorted with the shallowest directory first.
+pub fn duplicate_dirs(
+    images: &[ScannedFile],
+    root: &Path,
+    weights: Option<&[f64]>,
+) -> Vec<Vec<PathBuf>> {
+    let groups = group_ids(images, weights);
+
+    let mut contents = BTreeMap::<&Path, BTreeSet<usize>>::new();
+    for ((path, _, _), group) in images.iter().zip(groups) {
+        let Some(parent) = Path::new(path).parent() else {
+            continue;
+        };
+        for dir in parent.ancestors().take_while(|dir| dir.starts_with(root)) {
+            contents.entry(dir).or_default().insert(group);
+        }
+    }
+
+    let mut dirs_by_contents = BTreeMap::<&BTreeSet<usize>, Vec<&Path>>::new();
+    for (dir, groups) in &contents {
+        dirs_by_contents.entry(groups).or_default().push(dir);
+    }
+
+    let mut duplicates = dirs_by_contents
+        .into_values()
+        .map(|dirs| {
+            // a directory with nothing but one subdirectory has the same
+            // contents as it, without being a copy of it
+            dirs.iter()
+                .filter(|dir| {
+                    !dirs
+                        .iter()
+                        .any(|other| other != *dir && other.starts_with(dir))
+                })
+                .copied()
+                .collect::<Vec<_>>()
+        })
+        .filter(|dirs| dirs.len() > 1)
+        .collect::<Vec<_>>();
+
+    let duplicate_dirs = duplicates.iter().flatten().copied().collect::<HashSet<_>>();
+    duplicates.retain(|dirs| {
+        !dirs.iter().all(|dir| {
+            dir.ancestors()
+                .skip(1)
+                .any(|ancestor| duplicate_dirs.contains(ancestor))
+        })
+    });
+
+    let mut duplicates = duplicates
+        .into_iter()
+        .map(|mut dirs| {
+            dirs.sort_by_key(|dir| (dir.components().count(), dir.to_path_buf()));
+            dirs.into_iter().map(Path::to_path_buf).collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    duplicates.sort();
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(path: &str, hash: u8) -> ScannedFile {
+        (path.to_string(), vec![hash, hash], None)
+    }
+
+    #[test]
+    fn finds_topmost_duplicate_trees() {
+        let images = [
+            image("root/photos/2019/a.png", 0x00),
+            image("root/photos/2019/b.png", 0xff),
+            image("root/photos/2020/c.png", 0x0f),
+            image("root/backup/photos/2019/a.png", 0x01),
+            image("root/backup/photos/2019/b.png", 0xff),
+            image("root/backup/photos/2020/c.png", 0x0f),
+            // only partly matches, so it isn't a duplicate
+            image("root/old/a.png", 0x00),
+            image("root/old/unrelated.png", 0x3c),
+        ];
+        let duplicates = duplicate_dirs(&images, Path::new("root"), None);
+        assert_eq!(
+            duplicates,
+            [vec![
+                PathBuf::from("root/photos"),
+                PathBuf::from("root/backup/photos")
+            ]]
+        );
+    }
+
+    #[test]
+    fn compares_animations_by_their_frames() {
+        // the same first frame, but nothing else in common
+        let animation =
+            |path: &str, frames: u8| (path.to_string(), vec![0x00, 0x00], Some(vec![frames; 8]));
+        let images = [
+            animation("root/a/x.gif", 0x00),
+            animation("root/b/x.gif", 0xff),
+        ];
+        assert!(duplicate_dirs(&images, Path::new("root"), None).is_empty());
+    }
+}
@@ -0,0 +1,41 @@
+use std::{io, path::PathBuf};
+
+use image::ImageError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImdedupError {
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{} doesn't exist", path.display())]
+    NotFound { path: PathBuf },
+    #[error("failed to decode {path}: {source}")]
+    Decode { path: String, source: ImageError },
+    #[error("{path} is a {format} image, but imdedup was built without the `{feature}` feature that decodes them")]
+    UnsupportedFormat {
+        path: String,
+        format: String,
+        feature: &'static str,
+    },
+    #[error("failed to download {url}: {message}")]
+    Download { url: String, message: String },
+    #[error("failed to read archive {}: {message}", path.display())]
+    Archive { path: PathBuf, message: String },
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("{path} is on a different device than {kept}")]
+    CrossDevice { path: String, kept: String },
+    #[error("failed to connect to the system log: {0}")]
+    Syslog(String),
+    #[cfg(feature = "tui")]
+    #[error("terminal error: {0}")]
+    Terminal(io::Error),
+}
+
+impl ImdedupError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
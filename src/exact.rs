@@ -0,0 +1,176 @@
+//! Hashing images by their exact contents instead of perceptually, for
+//! `--exact`.
+
+use std::{fs, io, path::Path, time::SystemTime};
+
+use image::{io::Reader as ImageReader, DynamicImage};
+
+use crate::{
+    decodable_format, download, error::ImdedupError, load_image, semaphore::Semaphore,
+    sniff_format, sniff_header_format, Args, ExactMode, HashedImage, LoadedImage, Source,
+};
+
+/// Hash an image so that only exact copies of it have the same hash. Returns
+/// `Ok(None)` if it isn't an image that can be decoded.
+pub fn hash(
+    source: &Source,
+    mode: ExactMode,
+    args: &Args,
+    io_limit: Option<&Semaphore>,
+) -> Result<Option<HashedImage>, ImdedupError> {
+    match mode {
+        ExactMode::Pixels => {
+            let Some(LoadedImage {
+                image,
+                dimensions,
+                file_size,
+                created_at,
+                ..
+            }) = load_image(source, args, io_limit)?
+            else {
+                return Ok(None);
+            };
+            Ok(Some(HashedImage {
+                hash: pixels_hash(&image),
+                signature: None,
+                dimensions,
+                file_size,
+                created_at,
+            }))
+        }
+        ExactMode::Bytes => bytes_hash(source, io_limit),
+    }
+}
+
+/// Hash the decoded pixels along with the size of the image. They're converted
+/// to 16-bit RGBA first, so the same pixels hash the same whatever format or
+/// color type they were stored as.
+fn pixels_hash(image: &DynamicImage) -> Vec<u8> {
+    /// How many rows are converted at once, so a large image never has a
+    /// whole 16-bit copy of it in memory.
+    const ROWS: u32 = 16;
+
+    let (width, height) = (image.width(), image.height());
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&width.to_le_bytes());
+    hasher.update(&height.to_le_bytes());
+    let mut bytes = [0; 8192];
+    for y in (0..height).step_by(ROWS as usize) {
+        let rows = image
+            .crop_imm(0, y, width, ROWS.min(height - y))
+            .to_rgba16();
+        for values in rows.as_raw().chunks(bytes.len() / 2) {
+            for (value, value_bytes) in values.iter().zip(bytes.chunks_exact_mut(2)) {
+                value_bytes.copy_from_slice(&value.to_le_bytes());
+            }
+            hasher.update(&bytes[..values.len() * 2]);
+        }
+    }
+    hasher.finalize().as_bytes().to_vec()
+}
+
+/// Hash the bytes of the file without decoding it. Only the header is read to
+/// find out the image's size, so this is much faster than hashing its pixels.
+fn bytes_hash(
+    source: &Source,
+    io_limit: Option<&Semaphore>,
+) -> Result<Option<HashedImage>, ImdedupError> {
+    let sniff = |bytes: &[u8], name: &str| {
+        decodable_format(
+            sniff_header_format(bytes, Path::new(name)),
+            &source.path_string(),
+        )
+    };
+    let (bytes, format, created_at) = match source {
+        Source::File(path) => {
+            let permit = io_limit.map(Semaphore::acquire);
+            // the start of the file is enough to tell whether it's an image,
+            // so files that aren't are never read whole
+            let format = sniff_format(path)?;
+            if format.is_none() {
+                return Ok(None);
+            }
+            let bytes = fs::read(path).map_err(|err| ImdedupError::io(path, err))?;
+            drop(permit);
+            let created_at = fs::metadata(path)
+                .and_then(|metadata| metadata.created())
+                .map_err(|err| ImdedupError::io(path, err))?;
+            (bytes, format, created_at)
+        }
+        // downloaded images are treated as the newest copy
+        Source::Url(url) => {
+            let permit = io_limit.map(Semaphore::acquire);
+            let bytes = download(url)?;
+            drop(permit);
+            let format = sniff(&bytes, url)?;
+            (bytes, format, SystemTime::now())
+        }
+        Source::ArchiveEntry { archive, name } => {
+            let permit = io_limit.map(Semaphore::acquire);
+            let bytes = archive.read(name)?;
+            drop(permit);
+            let format = sniff(&bytes, name)?;
+            (bytes, format, archive.created_at)
+        }
+    };
+
+    let Some(format) = format else {
+        return Ok(None);
+    };
+    let dimensions = ImageReader::with_format(io::Cursor::new(&bytes), format)
+        .into_dimensions()
+        .map_err(|err| ImdedupError::Decode {
+            path: source.path_string(),
+            source: err,
+        })?;
+    Ok(Some(HashedImage {
+        hash: blake3::hash(&bytes).as_bytes().to_vec(),
+        signature: None,
+        dimensions,
+        file_size: bytes.len() as u64,
+        created_at,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_hash_ignores_color_type() {
+        let rgb = DynamicImage::ImageRgb8(image::RgbImage::from_fn(4, 2, |x, y| {
+            image::Rgb([x as u8 * 50, y as u8 * 100, 7])
+        }));
+        let rgba = DynamicImage::ImageRgba8(rgb.to_rgba8());
+        assert_eq!(pixels_hash(&rgb), pixels_hash(&rgba));
+
+        let mut edited = rgb.to_rgb8();
+        edited.get_pixel_mut(3, 1)[2] += 1;
+        assert_ne!(
+            pixels_hash(&rgb),
+            pixels_hash(&DynamicImage::ImageRgb8(edited))
+        );
+
+        // the same pixels, laid out differently
+        let tall = DynamicImage::ImageRgb8(
+            image::RgbImage::from_raw(2, 4, rgb.to_rgb8().into_raw()).unwrap(),
+        );
+        assert_ne!(pixels_hash(&rgb), pixels_hash(&tall));
+    }
+
+    #[test]
+    fn pixels_hash_covers_every_row() {
+        // more rows than are converted at once, and wide enough that a row
+        // doesn't fit in one update
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(1500, 40, |x, y| {
+            image::Rgb([x as u8, y as u8, (x ^ y) as u8])
+        }));
+        let mut expected = blake3::Hasher::new();
+        expected.update(&1500u32.to_le_bytes());
+        expected.update(&40u32.to_le_bytes());
+        for value in image.to_rgba16().into_raw() {
+            expected.update(&value.to_le_bytes());
+        }
+        assert_eq!(pixels_hash(&image), expected.finalize().as_bytes());
+    }
+}
@@ -0,0 +1,62 @@
+//! Clustering the pairs of matching images found during a scan into groups, for
+//! `--min-group-size` and `--tui`.
+
+use std::collections::HashMap;
+
+/// Put the images that match each other, directly or through other images,
+/// into groups, telling images apart by their path. Groups and the images in
+/// them are sorted by path.
+pub fn groups<T>(matches: Vec<(T, T)>, path: impl Fn(&T) -> &str) -> Vec<Vec<T>> {
+    let mut images = Vec::<T>::new();
+    let mut ids = HashMap::<String, usize>::new();
+    let mut parents = Vec::<usize>::new();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    for (a, b) in matches {
+        let [a, b] = [a, b].map(|image| {
+            *ids.entry(path(&image).to_string()).or_insert_with(|| {
+                images.push(image);
+                parents.push(parents.len());
+                parents.len() - 1
+            })
+        });
+        let (a, b) = (root(&mut parents, a), root(&mut parents, b));
+        parents[a.max(b)] = a.min(b);
+    }
+
+    let mut groups = HashMap::<usize, Vec<T>>::new();
+    for (i, image) in images.into_iter().enumerate() {
+        groups.entry(root(&mut parents, i)).or_default().push(image);
+    }
+    let mut groups = groups
+        .into_values()
+        .map(|mut group| {
+            group.sort_by(|a, b| path(a).cmp(path(b)));
+            group
+        })
+        .collect::<Vec<_>>();
+    groups.sort_by(|a, b| path(&a[0]).cmp(path(&b[0])));
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_matches_transitively() {
+        let matches = [("c.png", "a.png"), ("x.png", "y.png"), ("b.png", "c.png")]
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .to_vec();
+        assert_eq!(
+            groups(matches, |path| path),
+            [vec!["a.png", "b.png", "c.png"], vec!["x.png", "y.png"]]
+        );
+    }
+}
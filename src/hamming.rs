@@ -0,0 +1,22 @@
+//! Counting the bits that differ between two hashes. It's in its own file so
+//! `benches/hamming.rs` can time it.
+
+pub fn hamming_distance(a: &[u8], b: &[u8]) -> u32 {
+    // comparing a word at a time lets count_ones compile to a single popcount
+    let a_words = a.chunks_exact(8);
+    let b_words = b.chunks_exact(8);
+    let tail_bits = a_words
+        .remainder()
+        .iter()
+        .zip(b_words.remainder())
+        .map(|(a, b)| (a ^ b).count_ones())
+        .sum::<u32>();
+
+    let mut diff_bits = tail_bits;
+    for (a, b) in a_words.zip(b_words) {
+        let a = u64::from_ne_bytes(a.try_into().unwrap());
+        let b = u64::from_ne_bytes(b.try_into().unwrap());
+        diff_bits += (a ^ b).count_ones();
+    }
+    diff_bits
+}
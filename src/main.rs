@@ -1,33 +1,547 @@
+mod animation;
+mod archive;
+mod checkpoint;
+mod copy;
+mod dirs;
+mod error;
+mod exact;
+mod groups;
+mod hamming;
+mod recency;
+mod report;
+mod selftest;
+mod semaphore;
+mod system_log;
+mod theme;
+#[cfg(feature = "tui")]
+mod tui;
+mod verify;
+mod weights;
+
 use std::{
+    borrow::Cow,
     cmp,
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap, HashSet},
     env, fs,
-    io::{self, Write},
+    io::{self, BufRead, Read, Write},
+    path::{Path, PathBuf},
     process,
     sync::atomic::{self, AtomicU64},
-    time::SystemTime,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
+use image::{
+    imageops::FilterType, io::Reader as ImageReader, DynamicImage, GenericImage, ImageError,
+    ImageFormat,
+};
+use log::{debug, trace};
 use parking_lot::Mutex;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+
+use crate::{error::ImdedupError, hamming::hamming_distance, semaphore::Semaphore, theme::Theme};
 
 fn invalid_usage() -> ! {
-    eprintln!("usage: {} <path> [--delete]", env::args().next().unwrap());
-    process::exit(1);
+    eprintln!(
+        "usage: {0} <path> [options]
+       {0} --archive <file.zip> [options]
+       {0} --compare <a> <b> [options]
+       {0} --compare-report <old manifest> <new manifest> [options]
+       {0} selftest [options]
+
+options:
+  -d, --delete                 delete one image of each duplicate pair
+  -n, --normalize              equalize images before hashing
+  -b, --blocklist <file>       report images matching any hash in the file
+      --url-list <file>        also scan the images at the URLs in the file
+      --archive <file.zip>     scan the images in a zip archive instead of a
+                               directory, without extracting it
+  -r, --recursive              also scan subdirectories
+      --skip-dir <name>        don't descend into directories with this name
+      --per-dir                with -r, scan each subdirectory on its own,
+                               without matching images across them
+      --descend-all            descend into hidden directories and the ones
+                               that are skipped by default, like node_modules
+  -a, --all-matches            list every earlier image each one is similar to
+      --order-by <path|hash|oldest>
+                               hash images in parallel but compare them in this
+                               order, so every run gives the same results
+      --since <time>           only scan files modified after a duration ago
+                               (like 12h or 7d) or a date (YYYY-MM-DD), and
+                               compare them against the older files
+      --prune-empty-dirs       after deleting, remove directories that were
+                               left empty
+      --verify                 after deleting, scan again and report any images
+                               that still match or can't be opened
+      --verify-bytes           before deleting an exact match of the same size,
+                               check that the files are byte-for-byte equal
+      --same-device-only       never delete a file whose match is kept on a
+                               different device or filesystem
+  -k, --keep <policy>          which image of a pair to keep when deleting:
+                               size (default) or highest-res
+      --suspicious-size <n>    how many images one hash can match before it's
+                               considered suspicious (default 100). hashes of
+                               blank or solid-color images always are, and
+                               --delete skips the matches of suspicious hashes
+      --delete-suspicious      also delete matches of suspicious hashes, like
+                               blank images
+      --detect-thumbnails      report images that are much smaller copies of
+                               others as thumbnails, and always delete those
+      --resize-match           report matches with the same aspect ratio at
+                               different sizes as resizes, and keep the larger
+      --filename-hint          note when a match is named like a copy, such as
+                               photo (1).jpg, and prefer deleting the copy
+      --readonly <dir>         never delete images in this directory, keeping
+                               them instead
+      --relative-to <dir>      show paths relative to this directory
+      --theme <theme>          default, high-contrast, colorblind, or none
+      --no-color               the same as --theme none
+      --resize-filter <filter> nearest, triangle, or lanczos (default)
+      --preserve-aspect        pad images to a square before hashing
+      --dct                    hash with a DCT, which is more robust to
+                               resizing and small edits
+      --fast-decode            decode large JPEGs at a reduced size, which is
+                               faster but changes hashes slightly
+      --center-crop <percent>  only hash the central part of each image
+      --animated-signature     match animated GIFs and WebPs by several of
+                               their frames instead of just the first
+      --exact <pixels|bytes>   only match images with exactly the same pixels
+                               or file contents, without perceptual hashing
+      --alpha-mode <ignore|flatten|separate>
+                               how transparency is hashed (default ignore)
+      --alpha-background <rrggbb>
+                               the color transparent images are flattened onto
+                               (default ffffff)
+      --weighted-distance      weigh each hash bit by how much it varies across
+                               the scanned images when comparing them
+      --io-threads <n>         how many files to read at once, separately from
+                               how many are decoded and hashed at once
+      --max-index <n>          only remember the n most recently matched images,
+                               so memory is bounded but older copies are missed
+      --tui                    after the scan, review each group of matching
+                               images in the terminal and pick which to delete
+  -u, --uniques-only           only print the paths of images that didn't match
+                               any other image
+      --count-only             only print how many images were dup, sim and
+                               uniq, separated by spaces
+      --copy-uniques-to <dir>  copy one image from each group of matching
+                               images, and every unique image, into the dir
+      --pairs <file>           write every matching pair of images to the file
+                               as tab-separated values
+      --manifest <file>        write every file that's left after the scan to
+                               the file, with its hash and size
+      --near-miss <bits>       also report images that are up to this many bits
+                               too different to count as similar
+      --percent                show how similar each sim and near image is as a
+                               percentage
+      --min-group-size <n>     after the scan, list the groups of matching
+                               images with at least n files instead of each
+                               match as it's found
+      --histogram              print how far each image is from its most
+                               similar image, to help choose a threshold
+      --duplicate-dirs         after a recursive scan, list directories whose
+                               images all match another directory's
+      --progress-file <file>   keep the file updated with the scan's progress
+      --checkpoint <file>      periodically save progress to the file, and
+                               resume from it if it already exists
+      --syslog                 send the start, progress and summary of the
+                               scan to the system log
+      --quiet                  with --syslog, don't print anything to stdout
+      --fail-on-dup            exit with 1 if any duplicate or similar images
+                               were found
+  -y, --yes, --force           don't warn before deleting",
+        env::args().next().unwrap()
+    );
+    ExitCode::InvalidArguments.exit();
+}
+
+/// What the process exits with, so scripts can tell finding duplicates apart
+/// from the scan failing or the options being wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExitCode {
+    /// Nothing was found, or matches were found without `--fail-on-dup`.
+    Clean = 0,
+    /// Matches were found with `--fail-on-dup`, an image was blocklisted, or
+    /// the images passed to `--compare` don't match.
+    CheckFailed = 1,
+    InvalidArguments = 2,
+    /// Some files couldn't be read or decoded, or the scan couldn't finish.
+    Errors = 3,
+}
+
+impl ExitCode {
+    fn exit(self) -> ! {
+        process::exit(self as i32)
+    }
+}
+
+/// Which image of a matching pair to keep when deleting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeepPolicy {
+    /// Keep the larger file.
+    Size,
+    /// Keep the image with the most pixels, then the larger file.
+    HighestRes,
+}
+
+/// What order images are compared in with `--order-by`, after they've all been
+/// hashed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OrderBy {
+    Path,
+    Hash,
+    /// The oldest file first.
+    Oldest,
+}
+
+/// What `--exact` compares images by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExactMode {
+    /// The decoded pixels, so the same image saved in different formats still
+    /// matches.
+    Pixels,
+    /// The bytes of the file.
+    Bytes,
+}
+
+/// What `--alpha-mode` does with transparency before hashing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AlphaMode {
+    /// Drop the alpha channel and hash whatever color is stored under
+    /// transparent pixels.
+    Ignore,
+    /// Draw the image over the `--alpha-background` color.
+    Flatten,
+    /// Hash the flattened image and the alpha channel on their own, one after
+    /// the other.
+    Separate,
 }
 
+/// What transparent images are drawn over, unless `--alpha-background` is
+/// given.
+const DEFAULT_ALPHA_BACKGROUND: [u8; 3] = [0xff, 0xff, 0xff];
+
+#[derive(Clone)]
 struct Args {
-    path: String,
+    /// The directory to scan, which is only unset with `--archive`.
+    path: Option<String>,
+    archive: Option<String>,
+    /// Two images to compare with each other instead of scanning.
+    compare: Option<(String, String)>,
+    /// Compare the manifests of two scans instead of scanning.
+    compare_report: Option<(String, String)>,
+    /// Check which kinds of edited copies are detected instead of scanning.
+    selftest: bool,
     delete: bool,
+    normalize: bool,
+    blocklist: Option<String>,
+    url_list: Option<String>,
+    recursive: bool,
+    skip_dirs: Vec<String>,
+    descend_all: bool,
+    per_dir: bool,
+    all_matches: bool,
+    order_by: Option<OrderBy>,
+    since: Option<SystemTime>,
+    keep: KeepPolicy,
+    readonly_dirs: Vec<String>,
+    detect_thumbnails: bool,
+    resize_match: bool,
+    filename_hint: bool,
+    suspicious_size: u64,
+    delete_suspicious: bool,
+    prune_empty_dirs: bool,
+    verify: bool,
+    verify_bytes: bool,
+    same_device_only: bool,
+    theme: &'static Theme,
+    relative_to: Option<PathBuf>,
+    checkpoint: Option<String>,
+    progress_file: Option<String>,
+    syslog: bool,
+    quiet: bool,
+    tui: bool,
+    uniques_only: bool,
+    count_only: bool,
+    copy_uniques_to: Option<String>,
+    histogram: bool,
+    duplicate_dirs: bool,
+    pairs: Option<String>,
+    manifest: Option<String>,
+    near_miss: Option<u32>,
+    percent: bool,
+    min_group_size: Option<usize>,
+    resize_filter: FilterType,
+    preserve_aspect: bool,
+    dct: bool,
+    fast_decode: bool,
+    center_crop: Option<u32>,
+    animated_signature: bool,
+    weighted_distance: bool,
+    alpha_mode: AlphaMode,
+    alpha_background: Option<[u8; 3]>,
+    exact: Option<ExactMode>,
+    io_threads: Option<usize>,
+    max_index: Option<usize>,
+    fail_on_dup: bool,
+    yes: bool,
 }
 
 fn parse_args() -> Args {
     let mut path = None;
     let mut delete = false;
+    let mut normalize = false;
+    let mut blocklist = None;
+    let mut url_list = None;
+    let mut archive = None;
+    let mut compare = None;
+    let mut compare_report = None;
+    let mut recursive = false;
+    let mut skip_dirs = Vec::new();
+    let mut readonly_dirs = Vec::new();
+    let mut detect_thumbnails = false;
+    let mut resize_match = false;
+    let mut filename_hint = false;
+    let mut suspicious_size = DEFAULT_SUSPICIOUS_SIZE;
+    let mut delete_suspicious = false;
+    let mut descend_all = false;
+    let mut per_dir = false;
+    let mut all_matches = false;
+    let mut order_by = None;
+    let mut since = None;
+    let mut keep = KeepPolicy::Size;
+    let mut keep_set = false;
+    let mut prune_empty_dirs = false;
+    let mut verify = false;
+    let mut verify_bytes = false;
+    let mut same_device_only = false;
+    let mut checkpoint = None;
+    let mut progress_file = None;
+    let mut syslog = false;
+    let mut quiet = false;
+    let mut tui = false;
+    let mut uniques_only = false;
+    let mut count_only = false;
+    let mut copy_uniques_to = None;
+    let mut histogram = false;
+    let mut duplicate_dirs = false;
+    let mut pairs = None;
+    let mut manifest = None;
+    let mut near_miss = None;
+    let mut percent = false;
+    let mut min_group_size = None;
+    let mut relative_to = None;
+    // https://no-color.org
+    let mut theme = if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        &Theme::NONE
+    } else {
+        &Theme::DEFAULT
+    };
+    let mut resize_filter = FilterType::Lanczos3;
+    let mut preserve_aspect = false;
+    let mut dct = false;
+    let mut fast_decode = false;
+    let mut center_crop = None;
+    let mut animated_signature = false;
+    let mut weighted_distance = false;
+    let mut exact = None;
+    let mut alpha_mode = AlphaMode::Ignore;
+    let mut alpha_background = None;
+    let mut io_threads = None;
+    let mut max_index = None;
+    let mut fail_on_dup = false;
+    let mut yes = false;
 
-    for arg in env::args().skip(1) {
+    let mut cli_args = env::args().skip(1).peekable();
+    let selftest = cli_args.next_if(|arg| arg == "selftest").is_some();
+    // options from the environment go first so the command line overrides them
+    let env_args = match env::var("IMDEDUP_OPTS") {
+        Ok(opts) => split_words(&opts).unwrap_or_else(|message| {
+            eprintln!("{} IMDEDUP_OPTS {message}", theme.err.paint("error:"));
+            ExitCode::InvalidArguments.exit();
+        }),
+        Err(_) => Vec::new(),
+    };
+    let mut args = env_args.into_iter().chain(cli_args);
+    while let Some(arg) = args.next() {
         match arg.as_str() {
             "--delete" | "-d" => delete = true,
+            "--normalize" | "-n" => normalize = true,
+            "--blocklist" | "-b" => {
+                blocklist = Some(args.next().unwrap_or_else(|| invalid_usage()))
+            }
+            "--url-list" => url_list = Some(args.next().unwrap_or_else(|| invalid_usage())),
+            "--compare" => {
+                let a = args.next().unwrap_or_else(|| invalid_usage());
+                let b = args.next().unwrap_or_else(|| invalid_usage());
+                compare = Some((a, b));
+            }
+            "--compare-report" => {
+                let old = args.next().unwrap_or_else(|| invalid_usage());
+                let new = args.next().unwrap_or_else(|| invalid_usage());
+                compare_report = Some((old, new));
+            }
+            "--archive" => archive = Some(args.next().unwrap_or_else(|| invalid_usage())),
+            "--recursive" | "-r" => recursive = true,
+            "--skip-dir" => skip_dirs.push(args.next().unwrap_or_else(|| invalid_usage())),
+            "--detect-thumbnails" => detect_thumbnails = true,
+            "--resize-match" => resize_match = true,
+            "--filename-hint" => filename_hint = true,
+            "--suspicious-size" => {
+                suspicious_size = args
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .filter(|&size| size >= 2)
+                    .unwrap_or_else(|| invalid_usage())
+            }
+            "--delete-suspicious" => delete_suspicious = true,
+            "--readonly" => readonly_dirs.push(args.next().unwrap_or_else(|| invalid_usage())),
+            "--per-dir" => per_dir = true,
+            "--descend-all" => descend_all = true,
+            "--all-matches" | "-a" => all_matches = true,
+            "--since" => {
+                let value = args.next().unwrap_or_else(|| invalid_usage());
+                since =
+                    Some(parse_since(&value, SystemTime::now()).unwrap_or_else(|| invalid_usage()));
+            }
+            "--order-by" => {
+                order_by = match args.next().as_deref() {
+                    Some("path") => Some(OrderBy::Path),
+                    Some("hash") => Some(OrderBy::Hash),
+                    Some("oldest") => Some(OrderBy::Oldest),
+                    _ => invalid_usage(),
+                }
+            }
+            "--keep" | "-k" => {
+                keep_set = true;
+                keep = match args.next().as_deref() {
+                    Some("size") => KeepPolicy::Size,
+                    Some("highest-res") => KeepPolicy::HighestRes,
+                    _ => invalid_usage(),
+                }
+            }
+            "--prune-empty-dirs" => prune_empty_dirs = true,
+            "--verify" => verify = true,
+            "--verify-bytes" => verify_bytes = true,
+            "--same-device-only" => same_device_only = true,
+            "--theme" => {
+                theme = args
+                    .next()
+                    .and_then(|name| Theme::from_name(&name))
+                    .unwrap_or_else(|| invalid_usage())
+            }
+            "--no-color" => theme = &Theme::NONE,
+            "--relative-to" => {
+                relative_to = Some(
+                    args.next()
+                        .and_then(|dir| std::path::absolute(dir).ok())
+                        .unwrap_or_else(|| invalid_usage()),
+                )
+            }
+            "--tui" => tui = true,
+            "--uniques-only" | "-u" => uniques_only = true,
+            "--count-only" => count_only = true,
+            "--copy-uniques-to" => {
+                copy_uniques_to = Some(args.next().unwrap_or_else(|| invalid_usage()))
+            }
+            "--histogram" => histogram = true,
+            "--duplicate-dirs" => duplicate_dirs = true,
+            "--pairs" => pairs = Some(args.next().unwrap_or_else(|| invalid_usage())),
+            "--manifest" => manifest = Some(args.next().unwrap_or_else(|| invalid_usage())),
+            "--percent" => percent = true,
+            "--near-miss" => {
+                let bits = args
+                    .next()
+                    .and_then(|bits| bits.parse().ok())
+                    .unwrap_or_else(|| invalid_usage());
+                if bits == 0 {
+                    invalid_usage();
+                }
+                near_miss = Some(bits);
+            }
+            "--min-group-size" => {
+                let size = args
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .unwrap_or_else(|| invalid_usage());
+                // a group always has at least two images
+                if size < 2 {
+                    invalid_usage();
+                }
+                min_group_size = Some(size);
+            }
+            "--progress-file" => {
+                progress_file = Some(args.next().unwrap_or_else(|| invalid_usage()))
+            }
+            "--checkpoint" => checkpoint = Some(args.next().unwrap_or_else(|| invalid_usage())),
+            "--syslog" => syslog = true,
+            "--quiet" => quiet = true,
+            "--resize-filter" => {
+                resize_filter = match args.next().as_deref() {
+                    Some("nearest") => FilterType::Nearest,
+                    Some("triangle") => FilterType::Triangle,
+                    Some("lanczos") => FilterType::Lanczos3,
+                    _ => invalid_usage(),
+                }
+            }
+            "--preserve-aspect" => preserve_aspect = true,
+            "--dct" => dct = true,
+            "--fast-decode" => fast_decode = true,
+            "--animated-signature" => animated_signature = true,
+            "--weighted-distance" => weighted_distance = true,
+            "--alpha-mode" => {
+                alpha_mode = match args.next().as_deref() {
+                    Some("ignore") => AlphaMode::Ignore,
+                    Some("flatten") => AlphaMode::Flatten,
+                    Some("separate") => AlphaMode::Separate,
+                    _ => invalid_usage(),
+                }
+            }
+            "--alpha-background" => {
+                alpha_background = Some(
+                    args.next()
+                        .and_then(|color| hex::decode(color.trim_start_matches('#')).ok())
+                        .and_then(|color| color.try_into().ok())
+                        .unwrap_or_else(|| invalid_usage()),
+                )
+            }
+            "--exact" => {
+                exact = match args.next().as_deref() {
+                    Some("pixels") => Some(ExactMode::Pixels),
+                    Some("bytes") => Some(ExactMode::Bytes),
+                    _ => invalid_usage(),
+                }
+            }
+            "--io-threads" => {
+                io_threads = Some(
+                    args.next()
+                        .and_then(|threads| threads.parse().ok())
+                        .filter(|&threads| threads > 0)
+                        .unwrap_or_else(|| invalid_usage()),
+                )
+            }
+            "--max-index" => {
+                max_index = Some(
+                    args.next()
+                        .and_then(|max_index| max_index.parse().ok())
+                        .filter(|&max_index| max_index > 0)
+                        .unwrap_or_else(|| invalid_usage()),
+                )
+            }
+            "--center-crop" => {
+                let percent = args
+                    .next()
+                    .and_then(|percent| percent.trim_end_matches('%').parse().ok())
+                    .unwrap_or_else(|| invalid_usage());
+                if !(1..=100).contains(&percent) {
+                    invalid_usage();
+                }
+                center_crop = Some(percent);
+            }
+            "--fail-on-dup" => fail_on_dup = true,
+            "--yes" | "--force" | "-y" => yes = true,
             _ => match path {
                 None => path = Some(arg),
                 Some(_) => invalid_usage(),
@@ -35,142 +549,3509 @@ fn parse_args() -> Args {
         }
     }
 
-    let path = path.unwrap_or_else(|| invalid_usage());
+    let scan_targets = [
+        path.is_some(),
+        archive.is_some(),
+        compare.is_some(),
+        compare_report.is_some(),
+        selftest,
+    ];
+    if scan_targets.iter().filter(|&&target| target).count() != 1 {
+        invalid_usage();
+    }
+    // the counts are meant to be read by scripts, so they're never colored
+    if count_only {
+        theme = &Theme::NONE;
+    }
+
+    let args = Args {
+        path,
+        archive,
+        compare,
+        compare_report,
+        selftest,
+        delete,
+        normalize,
+        blocklist,
+        url_list,
+        recursive,
+        skip_dirs,
+        descend_all,
+        per_dir,
+        all_matches,
+        order_by,
+        since,
+        keep,
+        readonly_dirs,
+        detect_thumbnails,
+        resize_match,
+        filename_hint,
+        suspicious_size,
+        delete_suspicious,
+        prune_empty_dirs,
+        verify,
+        verify_bytes,
+        same_device_only,
+        theme,
+        relative_to,
+        checkpoint,
+        progress_file,
+        syslog,
+        quiet,
+        tui,
+        uniques_only,
+        count_only,
+        copy_uniques_to,
+        histogram,
+        duplicate_dirs,
+        pairs,
+        manifest,
+        near_miss,
+        percent,
+        min_group_size,
+        resize_filter,
+        preserve_aspect,
+        dct,
+        fast_decode,
+        center_crop,
+        animated_signature,
+        weighted_distance,
+        alpha_mode,
+        alpha_background,
+        exact,
+        io_threads,
+        max_index,
+        fail_on_dup,
+        yes,
+    };
+
+    if let Err(message) = validate_args(&args, keep_set) {
+        eprintln!("{} {message}", args.theme.err.paint("error:"));
+        ExitCode::InvalidArguments.exit();
+    }
 
-    Args { path, delete }
+    args
 }
 
-struct StoredImage {
-    pub path: String,
-    // used for choosing which file to delete
-    pub file_size: u64,
-    pub created_at: SystemTime,
+/// Split a string into words the way a shell would, for `IMDEDUP_OPTS`. Words
+/// are separated by whitespace, single quotes keep everything inside them as
+/// is, and a backslash escapes the next character outside of single quotes.
+fn split_words(s: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("has an unterminated '".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("has an unterminated \"".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("has an unterminated \"".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("ends with a \\".to_string()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
 }
 
-fn main() {
-    let args = parse_args();
+/// Reject combinations of options that conflict or wouldn't do anything, since
+/// silently ignoring them would be surprising.
+fn validate_args(args: &Args, keep_set: bool) -> Result<(), String> {
+    if args.delete && args.uniques_only {
+        return Err("--delete can't be combined with --uniques-only, since deleted files wouldn't be reported".to_string());
+    }
+    if args.delete && args.histogram {
+        return Err("--histogram is read-only and can't be combined with --delete".to_string());
+    }
+    if args.per_dir {
+        if !args.recursive {
+            return Err("--per-dir only applies with --recursive".to_string());
+        }
+        if args.url_list.is_some() {
+            return Err(
+                "--per-dir can't be combined with --url-list, since urls aren't in any directory"
+                    .to_string(),
+            );
+        }
+        if args.duplicate_dirs {
+            return Err("--per-dir never compares directories with each other, so it can't be combined with --duplicate-dirs".to_string());
+        }
+        let one_file_options = [
+            (args.checkpoint.is_some(), "--checkpoint"),
+            (args.pairs.is_some(), "--pairs"),
+            (args.manifest.is_some(), "--manifest"),
+            (args.progress_file.is_some(), "--progress-file"),
+        ];
+        if let Some((_, option)) = one_file_options.iter().find(|(set, _)| *set) {
+            return Err(format!(
+                "{option} writes a single file, so it can't be combined with --per-dir"
+            ));
+        }
+    }
+    if args.duplicate_dirs {
+        if !args.recursive {
+            return Err("--duplicate-dirs only applies with --recursive".to_string());
+        }
+        if args.delete {
+            return Err(
+                "--duplicate-dirs is read-only and can't be combined with --delete".to_string(),
+            );
+        }
+        if args.uniques_only {
+            return Err("--duplicate-dirs can't be combined with --uniques-only, since directories wouldn't be reported".to_string());
+        }
+        if args.checkpoint.is_some() {
+            return Err("--duplicate-dirs needs every image to be scanned in one run, so it can't be combined with --checkpoint".to_string());
+        }
+    }
+    if args.exact.is_some() {
+        let perceptual_options = [
+            (args.normalize, "--normalize"),
+            (args.preserve_aspect, "--preserve-aspect"),
+            (args.dct, "--dct"),
+            (args.fast_decode, "--fast-decode"),
+            (args.center_crop.is_some(), "--center-crop"),
+            (args.animated_signature, "--animated-signature"),
+            (args.weighted_distance, "--weighted-distance"),
+            (args.alpha_mode != AlphaMode::Ignore, "--alpha-mode"),
+            (args.blocklist.is_some(), "--blocklist"),
+            (args.near_miss.is_some(), "--near-miss"),
+            (args.percent, "--percent"),
+            (args.histogram, "--histogram"),
+            (args.detect_thumbnails, "--detect-thumbnails"),
+            (args.resize_match, "--resize-match"),
+            (args.compare.is_some(), "--compare"),
+            (args.selftest, "selftest"),
+        ];
+        if let Some((_, option)) = perceptual_options.iter().find(|(set, _)| *set) {
+            return Err(format!(
+                "{option} only applies to perceptual hashes, so it can't be combined with --exact"
+            ));
+        }
+    }
+    if args.weighted_distance {
+        if args.checkpoint.is_some() {
+            return Err("--weighted-distance learns from every image in one run, so it can't be combined with --checkpoint".to_string());
+        }
+        if args.compare.is_some() || args.selftest {
+            return Err(
+                "--weighted-distance needs a whole set of images to learn from".to_string(),
+            );
+        }
+    }
+    if args.manifest.is_some() && args.checkpoint.is_some() {
+        return Err("--manifest needs every image to be scanned in one run, so it can't be combined with --checkpoint".to_string());
+    }
+    if args.min_group_size.is_some() {
+        if args.compare.is_some() || args.compare_report.is_some() || args.selftest {
+            return Err("--min-group-size only applies when scanning".to_string());
+        }
+        if args.checkpoint.is_some() {
+            return Err("--min-group-size needs every match from one scan to group them, so it can't be combined with --checkpoint".to_string());
+        }
+        if args.uniques_only {
+            return Err("--min-group-size can't be combined with --uniques-only, since groups wouldn't be listed".to_string());
+        }
+    }
+    if args.uniques_only && args.near_miss.is_some() {
+        return Err("--near-miss can't be combined with --uniques-only, since near misses wouldn't be reported".to_string());
+    }
+    if args.compare.is_some() && (args.delete || args.copy_uniques_to.is_some()) {
+        return Err(
+            "--compare only compares two images, so it can't delete or copy anything".to_string(),
+        );
+    }
+    if args.compare_report.is_some() && (args.delete || args.copy_uniques_to.is_some()) {
+        return Err(
+            "--compare-report only reads two manifests, so it can't delete or copy anything"
+                .to_string(),
+        );
+    }
+    if args.selftest && (args.delete || args.copy_uniques_to.is_some()) {
+        return Err(
+            "selftest only uses its own temporary images, so it can't delete or copy anything"
+                .to_string(),
+        );
+    }
+    if args.archive.is_some() {
+        if args.delete {
+            return Err("--archive is read-only and can't be combined with --delete".to_string());
+        }
+        if args.copy_uniques_to.is_some() {
+            return Err("--copy-uniques-to can't copy images out of an --archive".to_string());
+        }
+        if args.recursive || args.since.is_some() {
+            return Err("--recursive and --since only apply when scanning a directory".to_string());
+        }
+    }
+    if args.uniques_only && args.percent {
+        return Err(
+            "--percent can't be combined with --uniques-only, since no matches are printed"
+                .to_string(),
+        );
+    }
+    if args.tui {
+        let conflicting_options = [
+            (args.delete, "--delete"),
+            (args.uniques_only, "--uniques-only"),
+            (args.count_only, "--count-only"),
+            (args.per_dir, "--per-dir"),
+            (args.archive.is_some(), "--archive"),
+            (args.checkpoint.is_some(), "--checkpoint"),
+        ];
+        if let Some((_, option)) = conflicting_options.iter().find(|(set, _)| *set) {
+            return Err(format!(
+                "--tui needs every match from one scan to choose what to delete itself, so it can't be combined with {option}"
+            ));
+        }
+    }
+    if args.count_only {
+        let printing_options = [
+            (args.uniques_only, "--uniques-only"),
+            (args.delete, "--delete"),
+            (args.histogram, "--histogram"),
+            (args.duplicate_dirs, "--duplicate-dirs"),
+            (args.near_miss.is_some(), "--near-miss"),
+            (args.percent, "--percent"),
+            (args.min_group_size.is_some(), "--min-group-size"),
+            (args.per_dir, "--per-dir"),
+        ];
+        if let Some((_, option)) = printing_options.iter().find(|(set, _)| *set) {
+            return Err(format!(
+                "{option} can't be combined with --count-only, since only the counts are printed"
+            ));
+        }
+    }
+    if args.syslog {
+        if args.compare.is_some() || args.compare_report.is_some() || args.selftest {
+            return Err("--syslog only applies when scanning".to_string());
+        }
+        if cfg!(not(unix)) {
+            return Err("--syslog is only supported on Unix".to_string());
+        }
+    }
+    if args.quiet {
+        if !args.syslog {
+            return Err("--quiet only applies with --syslog".to_string());
+        }
+        let printing_options = [
+            (args.uniques_only, "--uniques-only"),
+            (args.count_only, "--count-only"),
+            (args.histogram, "--histogram"),
+            (args.duplicate_dirs, "--duplicate-dirs"),
+            (args.near_miss.is_some(), "--near-miss"),
+            (args.percent, "--percent"),
+            (args.min_group_size.is_some(), "--min-group-size"),
+            (args.tui, "--tui"),
+            (args.verify, "--verify"),
+        ];
+        if let Some((_, option)) = printing_options.iter().find(|(set, _)| *set) {
+            return Err(format!(
+                "{option} can't be combined with --quiet, since nothing is printed"
+            ));
+        }
+    }
+    if args.max_index.is_some() {
+        if args.uniques_only {
+            return Err("--uniques-only can't be combined with --max-index, since forgotten images would be left out".to_string());
+        }
+        if args.copy_uniques_to.is_some() {
+            return Err("--copy-uniques-to can't be combined with --max-index, since forgotten images would be left out".to_string());
+        }
+    }
+    if args.alpha_background.is_some() && args.alpha_mode == AlphaMode::Ignore {
+        return Err(
+            "--alpha-background only applies with --alpha-mode flatten or separate".to_string(),
+        );
+    }
+    if args.delete_suspicious && !args.delete {
+        return Err("--delete-suspicious only applies with --delete".to_string());
+    }
+    if !args.readonly_dirs.is_empty() && !args.delete && !args.tui {
+        return Err("--readonly only applies with --delete or --tui".to_string());
+    }
+    if args.prune_empty_dirs && !args.delete {
+        return Err("--prune-empty-dirs only applies with --delete".to_string());
+    }
+    if args.verify && !args.delete {
+        return Err("--verify only applies with --delete".to_string());
+    }
+    if args.verify_bytes && !args.delete {
+        return Err("--verify-bytes only applies with --delete".to_string());
+    }
+    if args.same_device_only {
+        if !args.delete && !args.tui {
+            return Err("--same-device-only only applies with --delete or --tui".to_string());
+        }
+        if cfg!(not(unix)) {
+            return Err("--same-device-only is only supported on Unix".to_string());
+        }
+    }
+    if keep_set && !args.delete && args.copy_uniques_to.is_none() {
+        return Err("--keep only applies with --delete or --copy-uniques-to".to_string());
+    }
+    if !args.recursive {
+        if !args.skip_dirs.is_empty() {
+            return Err("--skip-dir only applies with --recursive".to_string());
+        }
+        if args.descend_all {
+            return Err("--descend-all only applies with --recursive".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Parse a `--since` value, which is either a duration before `now` (like `12h`
+/// or `7d`) or a `YYYY-MM-DD` date in UTC.
+fn parse_since(value: &str, now: SystemTime) -> Option<SystemTime> {
+    let unit_start = value.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = value.split_at(unit_start);
+    let unit_secs = match unit {
+        "s" => Some(1),
+        "m" => Some(60),
+        "h" => Some(60 * 60),
+        "d" => Some(60 * 60 * 24),
+        "w" => Some(60 * 60 * 24 * 7),
+        _ => None,
+    };
+    if let (Ok(number), Some(unit_secs)) = (number.parse::<u64>(), unit_secs) {
+        return now.checked_sub(Duration::from_secs(number.checked_mul(unit_secs)?));
+    }
 
-    let hasher = image_hasher::HasherConfig::new().to_hasher();
-    let hashes = Mutex::new(BTreeMap::<Vec<u8>, StoredImage>::new());
+    let mut parts = value.split('-');
+    let year = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?.parse::<i64>().ok()?;
+    let day = parts.next()?.parse::<i64>().ok()?;
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if is_leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if parts.next().is_some() || !(1..=days_in_month).contains(&day) {
+        return None;
+    }
 
-    let mut file_paths = Vec::new();
-    for entry in fs::read_dir(args.path).unwrap() {
-        let entry = entry.unwrap();
-        if !entry.file_type().unwrap().is_file() {
-            continue;
+    // https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let secs = u64::try_from(days).ok()? * 60 * 60 * 24;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Crop the image to the given percentage of its width and height, keeping the
+/// center.
+fn center_crop(image: &DynamicImage, percent: u32) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    let crop_width = (width * percent / 100).max(1);
+    let crop_height = (height * percent / 100).max(1);
+    image.crop_imm(
+        (width - crop_width) / 2,
+        (height - crop_height) / 2,
+        crop_width,
+        crop_height,
+    )
+}
+
+/// Pad the image with black borders to make it square, so the hasher doesn't
+/// stretch it and images with different aspect ratios don't collide.
+fn pad_to_square(image: &DynamicImage) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    if width == height {
+        return image.clone();
+    }
+    let size = width.max(height);
+    let mut padded = DynamicImage::new(size, size, image.color());
+    padded
+        .copy_from(image, (size - width) / 2, (size - height) / 2)
+        .unwrap();
+    padded
+}
+
+/// Draw the image over a solid background color, so how transparent pixels
+/// look doesn't depend on the color a program happened to store under them.
+/// The blending is done in integers, so it's the same on every platform.
+fn flatten_alpha(image: &DynamicImage, background: [u8; 3]) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image.clone();
+    }
+    let rgba = image.to_rgba8();
+    DynamicImage::ImageRgb8(image::RgbImage::from_fn(
+        rgba.width(),
+        rgba.height(),
+        |x, y| {
+            let [r, g, b, a] = rgba.get_pixel(x, y).0;
+            let blend = |color: u8, background: u8| {
+                let (color, background, a) = (color as u32, background as u32, a as u32);
+                ((color * a + background * (255 - a) + 127) / 255) as u8
+            };
+            image::Rgb([
+                blend(r, background[0]),
+                blend(g, background[1]),
+                blend(b, background[2]),
+            ])
+        },
+    ))
+}
+
+/// The alpha channel as a grayscale image, which is white where the image is
+/// opaque.
+fn alpha_channel(image: &DynamicImage) -> DynamicImage {
+    let rgba = image.to_rgba8();
+    DynamicImage::ImageLuma8(image::GrayImage::from_fn(
+        rgba.width(),
+        rgba.height(),
+        |x, y| image::Luma([rgba.get_pixel(x, y)[3]]),
+    ))
+}
+
+fn build_hasher(args: &Args) -> image_hasher::Hasher {
+    let mut hasher_config = image_hasher::HasherConfig::new().resize_filter(args.resize_filter);
+    if args.dct {
+        hasher_config = hasher_config.preproc_dct();
+    }
+    hasher_config.to_hasher()
+}
+
+/// The options that change what hash an image gets, for making sure hashes
+/// from a checkpoint were made the same way as new ones.
+fn hash_settings(args: &Args) -> String {
+    let center_crop = args
+        .center_crop
+        .map_or("none".to_string(), |percent| percent.to_string());
+    let background = hex::encode(args.alpha_background.unwrap_or(DEFAULT_ALPHA_BACKGROUND));
+    let alpha_mode = match args.alpha_mode {
+        AlphaMode::Ignore => "ignore".to_string(),
+        AlphaMode::Flatten => format!("flatten-{background}"),
+        AlphaMode::Separate => format!("separate-{background}"),
+    };
+    format!(
+        "resize-filter={:?} dct={} normalize={} preserve-aspect={} center-crop={center_crop} fast-decode={} animated-signature={} exact={} alpha-mode={alpha_mode}",
+        args.resize_filter,
+        args.dct as u8,
+        args.normalize as u8,
+        args.preserve_aspect as u8,
+        args.fast_decode as u8,
+        args.animated_signature as u8,
+        args.exact
+            .map_or("none".to_string(), |mode| format!("{mode:?}").to_lowercase()),
+    )
+}
+
+/// Apply the preprocessing options to the image and hash it. With
+/// `--alpha-mode separate`, the alpha channel's hash comes after the color's,
+/// so both have to match for the whole hash to.
+fn hash_image(hasher: &image_hasher::Hasher, image: DynamicImage, args: &Args) -> Vec<u8> {
+    let background = args.alpha_background.unwrap_or(DEFAULT_ALPHA_BACKGROUND);
+    match args.alpha_mode {
+        AlphaMode::Ignore => hash_preprocessed(hasher, image, args),
+        AlphaMode::Flatten => hash_preprocessed(hasher, flatten_alpha(&image, background), args),
+        AlphaMode::Separate => {
+            let alpha = alpha_channel(&image);
+            let mut hash = hash_preprocessed(hasher, flatten_alpha(&image, background), args);
+            hash.extend(hash_preprocessed(hasher, alpha, args));
+            hash
         }
-        let path = entry.path();
-        file_paths.push(path);
     }
-    let file_count = file_paths.len();
+}
+
+fn hash_preprocessed(hasher: &image_hasher::Hasher, image: DynamicImage, args: &Args) -> Vec<u8> {
+    let image = if let Some(percent) = args.center_crop {
+        center_crop(&image, percent)
+    } else {
+        image
+    };
+    let image = if args.normalize {
+        normalize(&image)
+    } else {
+        image
+    };
+    let image = if args.preserve_aspect {
+        pad_to_square(&image)
+    } else {
+        image
+    };
+    hasher.hash_image(&image).as_bytes().to_vec()
+}
 
-    let dup_count = AtomicU64::new(0);
-    let sim_count = AtomicU64::new(0);
-    let uniq_count = AtomicU64::new(0);
+/// How often the checkpoint is saved with `--checkpoint`.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
 
-    let processed_count = AtomicU64::new(0);
+/// How often the progress file is written with `--progress-file`.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
 
-    file_paths.into_par_iter().for_each(|path| {
-        let Ok(image) = image::open(&path) else {
-            return;
-        };
-        let hash = hasher.hash_image(&image);
-        let hash = hash.as_bytes().to_vec();
+/// Replace the progress file with how many of the files have been scanned, like
+/// `150/600 25.0%`. It's written to a temporary file and renamed so that
+/// anything polling it never sees it half-written.
+fn write_progress(path: &Path, scanned: u64, total: u64) -> Result<(), ImdedupError> {
+    let percent = if total == 0 {
+        100.
+    } else {
+        scanned as f64 / total as f64 * 100.
+    };
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, format!("{scanned}/{total} {percent:.1}%\n"))
+        .map_err(|err| ImdedupError::io(&temp_path, err))?;
+    fs::rename(&temp_path, path).map_err(|err| ImdedupError::io(path, err))
+}
 
-        let path_string = path.to_string_lossy().to_string();
+/// The maximum number of differing bits for two hashes to be considered similar.
+const SIMILARITY_THRESHOLD: u32 = 5;
 
-        let mut hashes = hashes.lock();
-        let dup_of = hashes.get(&hash);
-        let mut sim_to = None;
+/// How many bits differ between two images. Two animations with
+/// `--animated-signature` are compared by their frames, and anything else by
+/// its hash, with each bit counted by its weight from `--weighted-distance`.
+fn match_distance(
+    a_hash: &[u8],
+    a_signature: Option<&[u8]>,
+    b_hash: &[u8],
+    b_signature: Option<&[u8]>,
+    weights: Option<&[f64]>,
+) -> u32 {
+    match (a_signature, b_signature, weights) {
+        (Some(a_signature), Some(b_signature), _) => {
+            animation::sequence_distance(a_signature, b_signature)
+        }
+        (_, _, Some(weights)) => weights::weighted_distance(a_hash, b_hash, weights),
+        _ => hamming_distance(a_hash, b_hash),
+    }
+}
+
+/// A stored image along with its hash.
+type HashEntry<'a> = (&'a Vec<u8>, &'a StoredImage);
+
+/// The images that are compared against, by their hash. There's only one per
+/// hash unless animations with `--animated-signature` share a first frame
+/// without the rest of their frames matching.
+type StoredImages = BTreeMap<Vec<u8>, Vec<StoredImage>>;
+
+/// Every stored image along with its hash.
+fn stored_images(hashes: &StoredImages) -> impl Iterator<Item = HashEntry<'_>> {
+    hashes
+        .iter()
+        .flat_map(|(hash, images)| images.iter().map(move |image| (hash, image)))
+}
 
-        for (other_hash, other_path) in hashes.iter() {
-            let mut diff_bits = 0;
-            for (a, b) in hash.iter().zip(other_hash.iter()) {
-                diff_bits += (a ^ b).count_ones();
+/// Store an image, in place of any stored image that it's an exact match of.
+fn store_image(hashes: &mut StoredImages, hash: Vec<u8>, image: StoredImage) {
+    let images = hashes.entry(hash).or_default();
+    images.retain(|other_image| {
+        match (image.signature.as_deref(), other_image.signature.as_deref()) {
+            (Some(signature), Some(other_signature)) => {
+                animation::sequence_distance(signature, other_signature) != 0
             }
-            if diff_bits <= 5 {
-                sim_to = Some(other_path);
+            _ => false,
+        }
+    });
+    images.push(image);
+}
+
+/// How similar two hashes are as a percentage of their bits that are the same,
+/// like `95% similar`.
+fn similarity_percent(diff_bits: u32, total_bits: u32) -> String {
+    let percent = 100. * (1. - diff_bits as f64 / total_bits as f64);
+    format!("{percent:.0}% similar")
+}
+
+/// Find the stored images that are similar to the hash, not including an exact
+/// match. Only the first one is returned unless `all` is set.
+fn find_similar<'a>(
+    hashes: &'a StoredImages,
+    hash: &[u8],
+    signature: Option<&[u8]>,
+    weights: Option<&[f64]>,
+    all: bool,
+) -> Vec<HashEntry<'a>> {
+    let mut similar = Vec::new();
+    for (other_hash, other_image) in stored_images(hashes) {
+        let distance = match_distance(
+            hash,
+            signature,
+            other_hash,
+            other_image.signature.as_deref(),
+            weights,
+        );
+        if other_hash == hash && distance == 0 {
+            continue;
+        }
+        if distance <= SIMILARITY_THRESHOLD {
+            similar.push((other_hash, other_image));
+            if !all {
                 break;
             }
         }
+    }
+    similar
+}
+
+/// Find the stored image with exactly the same hash and the ones that are
+/// similar to it. Since an exact match already decides how the image is
+/// classified, the similar ones are only searched for if there's no exact match
+/// or `list_similar` is set. An animation whose first frame is the same as
+/// another's is only an exact match if the rest of its frames are too.
+fn find_matches<'a>(
+    hashes: &'a StoredImages,
+    hash: &[u8],
+    signature: Option<&[u8]>,
+    weights: Option<&[f64]>,
+    all: bool,
+    list_similar: bool,
+) -> (Option<HashEntry<'a>>, Vec<HashEntry<'a>>) {
+    // weights don't matter here, since identical hashes are always 0 apart
+    let dup_of = hashes.get_key_value(hash).and_then(|(other_hash, images)| {
+        images
+            .iter()
+            .find(|other_image| {
+                match_distance(
+                    hash,
+                    signature,
+                    other_hash,
+                    other_image.signature.as_deref(),
+                    None,
+                ) == 0
+            })
+            .map(|other_image| (other_hash, other_image))
+    });
+    let sim_to = if dup_of.is_none() || list_similar {
+        find_similar(hashes, hash, signature, weights, all)
+    } else {
+        Vec::new()
+    };
+    (dup_of, sim_to)
+}
+
+/// The default for `--suspicious-size`.
+const DEFAULT_SUSPICIOUS_SIZE: u64 = 100;
+
+/// Whether a hash has almost no bits set or almost all of them, which is what
+/// blank and solid-color images hash to. Those all match each other without
+/// being copies of each other.
+fn is_degenerate_hash(hash: &[u8]) -> bool {
+    let total_bits = hash.len() as u32 * 8;
+    let ones = hash.iter().map(|byte| byte.count_ones()).sum::<u32>();
+    ones <= 2 || ones + 2 >= total_bits
+}
+
+/// Find the stored images that are more than `SIMILARITY_THRESHOLD` but at most
+/// `SIMILARITY_THRESHOLD + bits` bits away from the hash, closest first.
+fn find_near_misses<'a>(
+    hashes: &'a StoredImages,
+    hash: &[u8],
+    signature: Option<&[u8]>,
+    weights: Option<&[f64]>,
+    bits: u32,
+) -> Vec<(&'a StoredImage, u32)> {
+    let mut near_misses = stored_images(hashes)
+        .map(|(other_hash, other_image)| {
+            (
+                other_image,
+                match_distance(
+                    hash,
+                    signature,
+                    other_hash,
+                    other_image.signature.as_deref(),
+                    weights,
+                ),
+            )
+        })
+        .filter(|&(_, distance)| {
+            distance > SIMILARITY_THRESHOLD && distance <= SIMILARITY_THRESHOLD + bits
+        })
+        .collect::<Vec<_>>();
+    near_misses.sort_by_key(|&(_, distance)| distance);
+    near_misses
+}
+
+/// Write the hash and size of every file left after the scan, for
+/// `--manifest`. It's a tab-separated file with a header line, like `--pairs`,
+/// sorted by path so manifests from different runs can be diffed.
+fn write_manifest(
+    path: &Path,
+    files: &BTreeMap<String, (Vec<u8>, u64)>,
+) -> Result<(), ImdedupError> {
+    let mut contents = "hash\tsize\tpath\n".to_string();
+    for (file_path, (hash, size)) in files {
+        contents.push_str(&format!(
+            "{}\t{size}\t{}\n",
+            hex::encode(hash),
+            escape_tsv(file_path)
+        ));
+    }
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, contents).map_err(|err| ImdedupError::io(&temp_path, err))?;
+    fs::rename(&temp_path, path).map_err(|err| ImdedupError::io(path, err))
+}
+
+/// Escape a path for a tab-separated file, the same way as in checkpoints.
+fn escape_tsv(path: &str) -> String {
+    path.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+/// Count how many hashes have their nearest other hash at each distance, so the
+/// count at index `n` is the number of hashes whose most similar hash is `n`
+/// bits away.
+fn nearest_distance_histogram(hashes: &[Vec<u8>], weights: Option<&[f64]>) -> Vec<u64> {
+    let nearest_distances = hashes
+        .par_iter()
+        .enumerate()
+        .filter_map(|(i, hash)| {
+            hashes
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| i != j)
+                .map(|(_, other_hash)| match weights {
+                    Some(weights) => weights::weighted_distance(hash, other_hash, weights),
+                    None => hamming_distance(hash, other_hash),
+                })
+                .min()
+        })
+        .collect::<Vec<_>>();
+
+    let Some(&max_distance) = nearest_distances.iter().max() else {
+        return Vec::new();
+    };
+    let mut histogram = vec![0; max_distance as usize + 1];
+    for distance in nearest_distances {
+        histogram[distance as usize] += 1;
+    }
+    histogram
+}
+
+fn print_histogram(histogram: &[u64]) {
+    const BAR_WIDTH: u64 = 40;
 
-        let mut previous_stored_image = None;
-        let display = if let Some(dup_of) = dup_of {
-            let dup_of_path = &dup_of.path;
-            previous_stored_image = Some(dup_of);
-            format!("\x1b[91mdup\x1b[m {path_string} == {dup_of_path}")
-        } else if let Some(sim_to) = sim_to {
-            let sim_to_path = &sim_to.path;
-            previous_stored_image = Some(sim_to);
-            format!("\x1b[93msim\x1b[m {path_string} ~= {sim_to_path}")
+    let max_count = histogram.iter().copied().max().unwrap_or(1);
+    println!("distance to nearest image (bits): images");
+    for (distance, &count) in histogram.iter().enumerate() {
+        // rounding up means any non-empty bucket gets at least one mark
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(max_count) as usize);
+        // mark the last distance that's still counted as similar
+        let marker = if distance as u32 == SIMILARITY_THRESHOLD {
+            " <- threshold"
         } else {
-            "".to_string()
+            ""
         };
+        println!("{distance:>3}: {count:>6} {bar}{marker}");
+    }
+}
 
-        let hash_hex = hex::encode(&hash);
+/// Read a file of hex-encoded hashes, one per line. Empty lines and lines
+/// starting with `#` are ignored.
+fn read_blocklist(path: &str) -> Result<Vec<Vec<u8>>, ImdedupError> {
+    let contents = fs::read_to_string(path).map_err(|err| ImdedupError::io(path, err))?;
 
-        let i = processed_count.fetch_add(1, atomic::Ordering::Relaxed);
-        print!(
-            "{}/{file_count} \x1b[90m{hash_hex}\x1b[m {display}\r",
-            i + 1
-        );
-        io::stdout().flush().unwrap();
-        if dup_of.is_some() || sim_to.is_some() {
-            println!();
+    let mut hashes = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
+        let hash = hex::decode(line).map_err(|err| {
+            ImdedupError::InvalidArgument(format!(
+                "invalid hash on line {} of blocklist {path}: {err}",
+                i + 1
+            ))
+        })?;
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
 
-        if dup_of.is_some() {
-            dup_count.fetch_add(1, atomic::Ordering::Relaxed);
-        } else if sim_to.is_some() {
-            sim_count.fetch_add(1, atomic::Ordering::Relaxed);
-        } else {
-            uniq_count.fetch_add(1, atomic::Ordering::Relaxed);
-        }
+/// Convert the image to grayscale and equalize its histogram, so differences in
+/// exposure or compression don't affect the hash as much.
+fn normalize(image: &DynamicImage) -> DynamicImage {
+    let mut gray = image.to_luma8();
 
-        let metadata = fs::metadata(&path).unwrap();
-        let file_size = metadata.len();
-        let created_at = metadata.created().unwrap();
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
 
-        let mut should_insert = true;
+    let mut cdf = [0u64; 256];
+    let mut total = 0;
+    for (value, count) in histogram.iter().enumerate() {
+        total += count;
+        cdf[value] = total;
+    }
+    let cdf_min = cdf.iter().copied().find(|&c| c > 0).unwrap_or(0);
 
-        if args.delete {
-            if let Some(previous_stored_image) = previous_stored_image {
-                // delete the smaller image
-                let path_to_delete = match file_size.cmp(&previous_stored_image.file_size) {
-                    cmp::Ordering::Equal => {
-                        // delete the newer image
-                        if previous_stored_image.created_at > created_at {
-                            &previous_stored_image.path
-                        } else {
-                            &path_string
-                        }
-                    }
-                    cmp::Ordering::Greater => &previous_stored_image.path,
-                    cmp::Ordering::Less => &path_string,
-                };
-                should_insert = path_to_delete != &path_string;
-                fs::remove_file(path_to_delete).unwrap();
+    // a single-color image has nothing to equalize
+    if total == cdf_min {
+        return DynamicImage::ImageLuma8(gray);
+    }
+
+    let mut lookup = [0u8; 256];
+    for (value, mapped) in lookup.iter_mut().enumerate() {
+        let scaled = cdf[value].saturating_sub(cdf_min) * 255 / (total - cdf_min);
+        *mapped = scaled as u8;
+    }
+    for pixel in gray.pixels_mut() {
+        pixel.0[0] = lookup[pixel.0[0] as usize];
+    }
+
+    DynamicImage::ImageLuma8(gray)
+}
+
+/// Read a file of URLs, one per line. Empty lines and lines starting with `#`
+/// are ignored.
+fn read_url_list(path: &str) -> Result<Vec<String>, ImdedupError> {
+    let contents = fs::read_to_string(path).map_err(|err| ImdedupError::io(path, err))?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Directories that are skipped in recursive mode unless `--descend-all` is
+/// passed, in addition to hidden directories.
+const DEFAULT_SKIPPED_DIRS: &[&str] = &["node_modules", "@eaDir", "__MACOSX", "$RECYCLE.BIN"];
+
+fn should_skip_dir(name: &str, args: &Args) -> bool {
+    if args.skip_dirs.iter().any(|skipped| skipped == name) {
+        return true;
+    }
+    !args.descend_all && (name.starts_with('.') || DEFAULT_SKIPPED_DIRS.contains(&name))
+}
+
+/// Add the paths of the files in the directory to `file_paths`, descending into
+/// subdirectories in recursive mode.
+fn collect_files(
+    dir: &Path,
+    args: &Args,
+    file_paths: &mut Vec<PathBuf>,
+) -> Result<(), ImdedupError> {
+    let read_dir_error = |err| ImdedupError::io(dir, err);
+    for entry in fs::read_dir(dir).map_err(read_dir_error)? {
+        let entry = entry.map_err(read_dir_error)?;
+        let file_type = entry
+            .file_type()
+            .map_err(|err| ImdedupError::io(entry.path(), err))?;
+        if file_type.is_dir() {
+            // skipping here means we never pay for walking junk directories
+            if args.recursive && !should_skip_dir(&entry.file_name().to_string_lossy(), args) {
+                collect_files(&entry.path(), args, file_paths)?;
             }
+        } else if file_type.is_file() {
+            // paths are kept as strings from here on, so a lossy one would be
+            // reported wrong and could even delete a different file
+            if entry.path().to_str().is_none() {
+                eprintln!(
+                    "{} skipping {}, its name isn't valid Unicode",
+                    args.theme.warning.paint("warning:"),
+                    entry.path().to_string_lossy()
+                );
+                continue;
+            }
+            file_paths.push(entry.path());
         }
+    }
+    Ok(())
+}
 
-        if should_insert {
-            hashes.insert(
-                hash,
-                StoredImage {
-                    path: path_string,
-                    file_size,
-                    created_at,
-                },
-            );
+/// Find the files to scan at the path that was passed, which can be a single
+/// file as well as a directory.
+fn collect_input(
+    path: &Path,
+    args: &Args,
+    file_paths: &mut Vec<PathBuf>,
+) -> Result<(), ImdedupError> {
+    let metadata = fs::metadata(path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => ImdedupError::NotFound {
+            path: path.to_owned(),
+        },
+        _ => ImdedupError::io(path, err),
+    })?;
+    if metadata.is_dir() {
+        collect_files(path, args, file_paths)
+    } else {
+        file_paths.push(path.to_owned());
+        Ok(())
+    }
+}
+
+/// Where an image to be scanned comes from.
+enum Source {
+    File(PathBuf),
+    Url(String),
+    ArchiveEntry {
+        archive: Arc<archive::Archive>,
+        name: String,
+    },
+}
+
+impl Source {
+    /// The path or url that identifies the image in the output.
+    fn path_string(&self) -> String {
+        match self {
+            Source::File(path) => path.to_string_lossy().to_string(),
+            Source::Url(url) => url.clone(),
+            Source::ArchiveEntry { archive, name } => archive.entry_path(name),
         }
-    });
+    }
+}
 
-    let dup_count = dup_count.load(atomic::Ordering::Relaxed);
-    let sim_count = sim_count.load(atomic::Ordering::Relaxed);
-    let uniq_count = uniq_count.load(atomic::Ordering::Relaxed);
+/// Work out the real format of a file from its magic bytes, falling back to the
+/// extension for formats that don't have any (like TGA). Returns `Ok(None)` if
+/// it isn't an image that can be decoded.
+fn sniff_format(path: &Path) -> Result<Option<ImageFormat>, ImdedupError> {
+    let file = fs::File::open(path).map_err(|err| ImdedupError::io(path, err))?;
+    let mut header = Vec::with_capacity(8192);
+    file.take(8192)
+        .read_to_end(&mut header)
+        .map_err(|err| ImdedupError::io(path, err))?;
+    decodable_format(sniff_header_format(&header, path), &path.to_string_lossy())
+}
 
-    // extra spaces at the end to remove any possible leftover characters :)
-    println!(
-        "{dup_count} \x1b[91mdup\x1b[m, {sim_count} \x1b[93msim\x1b[m, {uniq_count} \x1b[96muniq\x1b[m        ",
-    );
+/// Work out the format of an image from the start of its contents, using the
+/// path's extension for formats without magic bytes. The format might not be
+/// one that can be decoded, see `decodable_format`.
+fn sniff_header_format(header: &[u8], path: &Path) -> Option<ImageFormat> {
+    match infer::get(header) {
+        Some(kind) if kind.matcher_type() == infer::MatcherType::Image => {
+            ImageFormat::from_mime_type(kind.mime_type())
+        }
+        // something we recognize that isn't an image, like a video or archive
+        Some(_) => None,
+        None => ImageFormat::from_path(path).ok(),
+    }
+}
+
+/// The cargo feature that lets the format be decoded, for the ones that are
+/// optional.
+fn format_feature(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Bmp => Some("bmp"),
+        ImageFormat::Dds => Some("dds"),
+        ImageFormat::OpenExr => Some("exr"),
+        ImageFormat::Farbfeld => Some("ff"),
+        ImageFormat::Gif => Some("gif"),
+        ImageFormat::Hdr => Some("hdr"),
+        ImageFormat::Ico => Some("ico"),
+        ImageFormat::Pnm => Some("pnm"),
+        ImageFormat::Qoi => Some("qoi"),
+        ImageFormat::Tga => Some("tga"),
+        ImageFormat::Tiff => Some("tiff"),
+        ImageFormat::WebP => Some("webp"),
+        _ => None,
+    }
+}
+
+/// Check that a sniffed format can be decoded. Images in a format whose
+/// feature imdedup was built without are an error rather than being skipped,
+/// so they aren't silently left out of the scan. Formats there isn't a feature
+/// for, like AVIF, are skipped like any other file that isn't an image.
+fn decodable_format(
+    format: Option<ImageFormat>,
+    path: &str,
+) -> Result<Option<ImageFormat>, ImdedupError> {
+    let Some(format) = format else {
+        return Ok(None);
+    };
+    if format.reading_enabled() {
+        return Ok(Some(format));
+    }
+    match format_feature(format) {
+        Some(feature) => Err(ImdedupError::UnsupportedFormat {
+            path: path.to_string(),
+            format: format!("{format:?}"),
+            feature,
+        }),
+        None => Ok(None),
+    }
+}
+
+/// The smallest width or height that `--fast-decode` will scale a JPEG down
+/// to, which leaves plenty of detail for an 8x8 hash even after cropping.
+const FAST_DECODE_MIN_SIZE: u16 = 256;
+
+/// Decode a JPEG at the smallest of 1/8, 1/4, 1/2, or full scale that's still
+/// at least `FAST_DECODE_MIN_SIZE` along one side, which skips most of the
+/// work for large images. Returns the image and its full-size dimensions, or
+/// `None` if the JPEG should be decoded normally instead.
+fn decode_jpeg_scaled(reader: impl io::Read) -> Option<(DynamicImage, (u32, u32))> {
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let dimensions = (info.width as u32, info.height as u32);
+    let (width, height) = decoder
+        .scale(FAST_DECODE_MIN_SIZE, FAST_DECODE_MIN_SIZE)
+        .ok()?;
+    let pixels = decoder.decode().ok()?;
+    let image = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => DynamicImage::ImageLuma8(image::GrayImage::from_raw(
+            width as u32,
+            height as u32,
+            pixels,
+        )?),
+        jpeg_decoder::PixelFormat::RGB24 => DynamicImage::ImageRgb8(image::RgbImage::from_raw(
+            width as u32,
+            height as u32,
+            pixels,
+        )?),
+        // these are rare enough to not be worth converting by hand
+        jpeg_decoder::PixelFormat::L16 | jpeg_decoder::PixelFormat::CMYK32 => return None,
+    };
+    Some((image, dimensions))
+}
+
+struct LoadedImage {
+    image: DynamicImage,
+    /// The full size of the image, which `--fast-decode` may have decoded at a
+    /// smaller size.
+    dimensions: (u32, u32),
+    file_size: u64,
+    created_at: SystemTime,
+    /// Frames sampled from animations with `--animated-signature`, and empty
+    /// for everything else.
+    frames: Vec<DynamicImage>,
+}
+
+/// What's kept of an image after hashing it, until it's compared.
+struct HashedImage {
+    hash: Vec<u8>,
+    signature: Option<Vec<u8>>,
+    dimensions: (u32, u32),
+    file_size: u64,
+    created_at: SystemTime,
+}
+
+impl LoadedImage {
+    fn new(image: DynamicImage, file_size: u64, created_at: SystemTime) -> Self {
+        Self {
+            dimensions: (image.width(), image.height()),
+            image,
+            file_size,
+            created_at,
+            frames: Vec::new(),
+        }
+    }
+}
+
+/// Whether to sample the frames of an image with this format for
+/// `--animated-signature`.
+fn is_animation_format(format: ImageFormat, args: &Args) -> bool {
+    args.animated_signature && matches!(format, ImageFormat::Gif | ImageFormat::WebP)
+}
+
+/// Decode an image along with the size and creation time that are used to
+/// decide which file to delete. Returns `Ok(None)` if the file isn't an image.
+/// With an `io_limit`, files, downloads, and archive entries are read into
+/// memory while holding one of its permits, and only decoded after giving it
+/// back.
+fn load_image(
+    source: &Source,
+    args: &Args,
+    io_limit: Option<&Semaphore>,
+) -> Result<Option<LoadedImage>, ImdedupError> {
+    let theme = args.theme;
+    let fast_decode_jpeg = |format: ImageFormat| args.fast_decode && format == ImageFormat::Jpeg;
+    match source {
+        Source::File(path) => {
+            let permit = io_limit.map(Semaphore::acquire);
+            let Some(format) = sniff_format(path)? else {
+                trace!("skipping {}, it isn't an image", path.display());
+                return Ok(None);
+            };
+            if let Ok(extension_format) = ImageFormat::from_path(path) {
+                if extension_format != format {
+                    eprintln!(
+                        "{} {} has the extension of a {} but is a {}",
+                        theme.warning.paint("warning:"),
+                        path.display(),
+                        extension_format.extensions_str()[0],
+                        format.extensions_str()[0],
+                    );
+                }
+            }
+
+            let metadata = fs::metadata(path).map_err(|err| ImdedupError::io(path, err))?;
+            let created_at = metadata
+                .created()
+                .map_err(|err| ImdedupError::io(path, err))?;
+
+            if permit.is_some() {
+                let bytes = fs::read(path).map_err(|err| ImdedupError::io(path, err))?;
+                drop(permit);
+                return decode_in_memory(bytes, format, &path.to_string_lossy(), created_at, args);
+            }
+
+            let reader = fs::File::open(path).map_err(|err| ImdedupError::io(path, err))?;
+            if fast_decode_jpeg(format) {
+                if let Some((image, dimensions)) = decode_jpeg_scaled(io::BufReader::new(reader)) {
+                    return Ok(Some(LoadedImage {
+                        image,
+                        dimensions,
+                        file_size: metadata.len(),
+                        created_at,
+                        frames: Vec::new(),
+                    }));
+                }
+            }
+            // reopened since the scaled decoder may have read part of it
+            let reader = fs::File::open(path).map_err(|err| ImdedupError::io(path, err))?;
+            let image = match ImageReader::with_format(io::BufReader::new(reader), format).decode()
+            {
+                Ok(image) => image,
+                Err(ImageError::Unsupported(err)) => {
+                    trace!("skipping {}, it can't be decoded: {err}", path.display());
+                    return Ok(None);
+                }
+                Err(ImageError::IoError(err)) => return Err(ImdedupError::io(path, err)),
+                Err(err) => {
+                    return Err(ImdedupError::Decode {
+                        path: path.to_string_lossy().to_string(),
+                        source: err,
+                    })
+                }
+            };
+            let mut loaded = LoadedImage::new(image, metadata.len(), created_at);
+            if is_animation_format(format, args) {
+                let reader = fs::File::open(path).map_err(|err| ImdedupError::io(path, err))?;
+                loaded.frames = animation::sampled_frames(io::BufReader::new(reader), format)
+                    .unwrap_or_default();
+            }
+            Ok(Some(loaded))
+        }
+        Source::Url(url) => {
+            let permit = io_limit.map(Semaphore::acquire);
+            let bytes = download(url)?;
+            drop(permit);
+            // a url was asked for by name, so it's an error if it isn't an image
+            let Some(format) = decodable_format(sniff_header_format(&bytes, Path::new(url)), url)?
+            else {
+                return Err(ImdedupError::Download {
+                    url: url.clone(),
+                    message: "it isn't an image".to_string(),
+                });
+            };
+            // downloaded images are treated as the newest copy
+            decode_in_memory(bytes, format, url, SystemTime::now(), args)
+        }
+        Source::ArchiveEntry { archive, name } => {
+            let permit = io_limit.map(Semaphore::acquire);
+            let bytes = archive.read(name)?;
+            drop(permit);
+            let Some(format) = decodable_format(
+                sniff_header_format(&bytes, Path::new(name)),
+                &archive.entry_path(name),
+            )?
+            else {
+                return Ok(None);
+            };
+            decode_in_memory(
+                bytes,
+                format,
+                &archive.entry_path(name),
+                archive.created_at,
+                args,
+            )
+        }
+    }
+}
+
+/// Decode an image that was already read into memory. `path` is only used for
+/// errors.
+fn decode_in_memory(
+    bytes: Vec<u8>,
+    format: ImageFormat,
+    path: &str,
+    created_at: SystemTime,
+    args: &Args,
+) -> Result<Option<LoadedImage>, ImdedupError> {
+    let file_size = bytes.len() as u64;
+    if args.fast_decode && format == ImageFormat::Jpeg {
+        if let Some((image, dimensions)) = decode_jpeg_scaled(bytes.as_slice()) {
+            return Ok(Some(LoadedImage {
+                image,
+                dimensions,
+                file_size,
+                created_at,
+                frames: Vec::new(),
+            }));
+        }
+    }
+    let frames = if is_animation_format(format, args) {
+        animation::sampled_frames(io::Cursor::new(bytes.as_slice()), format).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let image = match ImageReader::with_format(io::Cursor::new(bytes), format).decode() {
+        Ok(image) => image,
+        Err(ImageError::Unsupported(_)) => return Ok(None),
+        Err(err) => {
+            return Err(ImdedupError::Decode {
+                path: path.to_string(),
+                source: err,
+            })
+        }
+    };
+    Ok(Some(LoadedImage {
+        frames,
+        ..LoadedImage::new(image, file_size, created_at)
+    }))
+}
+
+/// The most that's read into memory for one image that isn't a plain file,
+/// like a download or an entry in an `--archive`, so a malicious one can't use
+/// up all the memory.
+const MAX_LOADED_SIZE: u64 = 256 * 1024 * 1024;
+
+#[cfg(feature = "url")]
+fn download(url: &str) -> Result<Vec<u8>, ImdedupError> {
+    let download_error = |err: ureq::Error| ImdedupError::Download {
+        url: url.to_string(),
+        message: err.to_string(),
+    };
+    let mut response = ureq::get(url).call().map_err(download_error)?;
+    response
+        .body_mut()
+        .with_config()
+        .limit(MAX_LOADED_SIZE)
+        .read_to_vec()
+        .map_err(download_error)
+}
+
+#[cfg(not(feature = "url"))]
+fn download(url: &str) -> Result<Vec<u8>, ImdedupError> {
+    Err(ImdedupError::Download {
+        url: url.to_string(),
+        message: "imdedup was built without the `url` feature".to_string(),
+    })
+}
+
+struct StoredImage {
+    pub path: String,
+    // used for choosing which file to delete
+    pub file_size: u64,
+    pub created_at: SystemTime,
+    pub dimensions: (u32, u32),
+    // images from urls can't be deleted
+    pub is_url: bool,
+    // images older than --since are only compared against
+    pub is_reference: bool,
+    // whether this image matched any other image, used for --uniques-only
+    pub matched: bool,
+    // the hashes of several frames with --animated-signature, if it's animated
+    pub signature: Option<Vec<u8>>,
+}
+
+impl StoredImage {
+    fn display_path(&self, args: &Args) -> Cow<'_, str> {
+        if self.is_url {
+            Cow::Borrowed(&self.path)
+        } else {
+            display_path(&self.path, args)
+        }
+    }
+}
+
+/// How a file's path is shown in the output, which is relative to
+/// `--relative-to` if the file is inside it. The real path is still used for
+/// everything else.
+fn display_path<'a>(path: &'a str, args: &Args) -> Cow<'a, str> {
+    let Some(base) = &args.relative_to else {
+        return Cow::Borrowed(path);
+    };
+    let Ok(absolute) = std::path::absolute(path) else {
+        return Cow::Borrowed(path);
+    };
+    match absolute.strip_prefix(base) {
+        Ok(relative) if relative.as_os_str().is_empty() => Cow::Borrowed("."),
+        Ok(relative) => Cow::Owned(relative.to_string_lossy().into_owned()),
+        Err(_) => Cow::Borrowed(path),
+    }
+}
+
+/// Remove the directories that are empty now, and then their parents if that
+/// left them empty too, without going above or removing `root`. Directories that
+/// still contain anything, even hidden files, are left alone. Returns how many
+/// were removed.
+fn prune_empty_dirs(dirs: HashSet<PathBuf>, root: &Path) -> usize {
+    let mut dirs = dirs.into_iter().collect::<Vec<_>>();
+    // deepest first, so a parent is only tried after its children
+    dirs.sort_by_key(|dir| cmp::Reverse(dir.components().count()));
+
+    let mut pruned = 0;
+    for dir in dirs {
+        let mut dir = dir.as_path();
+        while dir != root && dir.starts_with(root) && fs::remove_dir(dir).is_ok() {
+            pruned += 1;
+            let Some(parent) = dir.parent() else {
+                break;
+            };
+            dir = parent;
+        }
+    }
+    pruned
+}
+
+/// Check that a file can still be opened and read from.
+fn check_readable(path: &str) -> Result<(), ImdedupError> {
+    fs::File::open(path)
+        .and_then(|mut file| file.read(&mut [0]))
+        .map(|_| ())
+        .map_err(|err| ImdedupError::io(path, err))
+}
+
+/// Check whether two files are on the same device, for `--same-device-only`.
+#[cfg(unix)]
+fn same_device(a: &str, b: &str) -> Result<bool, ImdedupError> {
+    use std::os::unix::fs::MetadataExt;
+
+    let device = |path: &str| {
+        fs::metadata(path)
+            .map(|metadata| metadata.dev())
+            .map_err(|err| ImdedupError::io(path, err))
+    };
+    Ok(device(a)? == device(b)?)
+}
+
+/// Whether two paths are hard links to the same file, which a previous run
+/// (or anything else) may have linked together. Errors count as not linked,
+/// since the files are checked again before anything is deleted.
+#[cfg(unix)]
+fn same_inode(a: &str, b: &str) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let inode = |path: &str| {
+        fs::metadata(path)
+            .map(|metadata| (metadata.dev(), metadata.ino()))
+            .ok()
+    };
+    matches!((inode(a), inode(b)), (Some(a), Some(b)) if a == b)
+}
+
+#[cfg(not(unix))]
+fn same_inode(_a: &str, _b: &str) -> bool {
+    false
+}
+
+// --same-device-only is rejected before scanning anywhere else
+#[cfg(not(unix))]
+fn same_device(_a: &str, _b: &str) -> Result<bool, ImdedupError> {
+    Ok(true)
+}
+
+/// Check whether two files have exactly the same bytes.
+fn same_contents(a: &str, b: &str) -> Result<bool, ImdedupError> {
+    let open = |path: &str| {
+        fs::File::open(path)
+            .map(io::BufReader::new)
+            .map_err(|err| ImdedupError::io(path, err))
+    };
+    let (mut a_reader, mut b_reader) = (open(a)?, open(b)?);
+
+    loop {
+        let a_buf = a_reader
+            .fill_buf()
+            .map_err(|err| ImdedupError::io(a, err))?;
+        let b_buf = b_reader
+            .fill_buf()
+            .map_err(|err| ImdedupError::io(b, err))?;
+        if a_buf.is_empty() || b_buf.is_empty() {
+            return Ok(a_buf.is_empty() && b_buf.is_empty());
+        }
+        let len = a_buf.len().min(b_buf.len());
+        if a_buf[..len] != b_buf[..len] {
+            return Ok(false);
+        }
+        a_reader.consume(len);
+        b_reader.consume(len);
+    }
+}
+
+/// Which of two matching images should be deleted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Deletion {
+    New,
+    Previous,
+}
+
+/// How much the keep policy wants to keep the image, compared with other images
+/// it matches. Ties are broken by keeping the larger file, and then the older
+/// one.
+fn keep_rank(image: &StoredImage, keep: KeepPolicy) -> impl Ord {
+    let pixels = match keep {
+        KeepPolicy::Size => 0,
+        KeepPolicy::HighestRes => image.dimensions.0 as u64 * image.dimensions.1 as u64,
+    };
+    (pixels, image.file_size, cmp::Reverse(image.created_at))
+}
+
+/// Compare two images by `--order-by`. Ties, and images that couldn't be
+/// hashed, are ordered by path, so the order is always the same.
+fn scan_order(
+    order_by: OrderBy,
+    (a_source, a_hashed): &(Source, Option<HashedImage>),
+    (b_source, b_hashed): &(Source, Option<HashedImage>),
+) -> cmp::Ordering {
+    let by_key = match (order_by, a_hashed, b_hashed) {
+        (OrderBy::Path, _, _) => cmp::Ordering::Equal,
+        (OrderBy::Hash, Some(a), Some(b)) => a.hash.cmp(&b.hash),
+        (OrderBy::Oldest, Some(a), Some(b)) => a.created_at.cmp(&b.created_at),
+        // images that couldn't be hashed don't have a key, so they go last
+        (_, a, b) => a.is_none().cmp(&b.is_none()),
+    };
+    by_key.then_with(|| a_source.path_string().cmp(&b_source.path_string()))
+}
+
+/// Choose which of two matching images to delete according to the keep policy.
+/// Images older than `--since` are always kept.
+fn choose_to_delete(new: &StoredImage, previous: &StoredImage, keep: KeepPolicy) -> Deletion {
+    if previous.is_reference {
+        return Deletion::New;
+    }
+    if keep_rank(new, keep) > keep_rank(previous, keep) {
+        Deletion::Previous
+    } else {
+        Deletion::New
+    }
+}
+
+/// How many times smaller than another image an image has to be along both
+/// sides to count as a thumbnail of it.
+const THUMBNAIL_SCALE: u64 = 2;
+
+/// Whether an image is a much lower resolution version of another matching one,
+/// for `--detect-thumbnails`.
+fn is_thumbnail_of(small: &StoredImage, large: &StoredImage) -> bool {
+    let (small_width, small_height) = small.dimensions;
+    let (large_width, large_height) = large.dimensions;
+    small_width as u64 * THUMBNAIL_SCALE <= large_width as u64
+        && small_height as u64 * THUMBNAIL_SCALE <= large_height as u64
+}
+
+/// Which image to delete if one is a thumbnail of the other, which overrides the
+/// keep policy. Images older than `--since` are still always kept.
+fn thumbnail_deletion(new: &StoredImage, previous: &StoredImage) -> Option<Deletion> {
+    if is_thumbnail_of(new, previous) {
+        Some(Deletion::New)
+    } else if is_thumbnail_of(previous, new) && !previous.is_reference {
+        Some(Deletion::Previous)
+    } else {
+        None
+    }
+}
+
+/// Whether an image is a smaller version of another matching one with the same
+/// aspect ratio, for `--resize-match`. Resizing rounds each side to a whole
+/// pixel, so the ratios only have to agree to within that.
+fn is_resize_of(small: &StoredImage, large: &StoredImage) -> bool {
+    let (small_width, small_height) = small.dimensions;
+    let (large_width, large_height) = large.dimensions;
+    if small_width >= large_width || small_height >= large_height {
+        return false;
+    }
+    let ratio_difference = (small_width as u64 * large_height as u64)
+        .abs_diff(small_height as u64 * large_width as u64);
+    ratio_difference <= large_width.max(large_height) as u64
+}
+
+/// Which image to delete if one is a resize of the other, which overrides the
+/// keep policy so the higher resolution is kept. Images older than `--since`
+/// are still always kept.
+fn resize_deletion(new: &StoredImage, previous: &StoredImage) -> Option<Deletion> {
+    if is_resize_of(new, previous) {
+        Some(Deletion::New)
+    } else if is_resize_of(previous, new) && !previous.is_reference {
+        Some(Deletion::Previous)
+    } else {
+        None
+    }
+}
+
+/// The name a file had before it was saved again as a copy, like `photo` for
+/// `photo (1)` or `photo_copy`, or `None` if it isn't named like a copy.
+fn original_stem(stem: &str) -> Option<&str> {
+    if let Some(original) = stem.strip_suffix("_copy") {
+        return Some(original);
+    }
+    let (original, number) = stem.strip_suffix(')')?.rsplit_once(" (")?;
+    (!number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit())).then_some(original)
+}
+
+/// Whether an image's file is named like a copy of another one's, like
+/// `photo (1).jpg` of `photo.jpg`, for `--filename-hint`.
+fn is_named_copy_of(copy: &StoredImage, original: &StoredImage) -> bool {
+    let stem = |image: &StoredImage| {
+        Path::new(&image.path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+    };
+    match (stem(copy), stem(original)) {
+        (Some(copy), Some(original)) => original_stem(&copy) == Some(original.as_str()),
+        _ => false,
+    }
+}
+
+/// Which image to delete if one is named like a copy of the other, which
+/// overrides the keep policy. Images older than `--since` are still always
+/// kept.
+fn filename_deletion(new: &StoredImage, previous: &StoredImage) -> Option<Deletion> {
+    if is_named_copy_of(new, previous) {
+        Some(Deletion::New)
+    } else if is_named_copy_of(previous, new) && !previous.is_reference {
+        Some(Deletion::Previous)
+    } else {
+        None
+    }
+}
+
+/// Change the choice of image to delete so that images in `--readonly`
+/// directories are kept, or return `None` if that leaves nothing that can be
+/// deleted.
+fn avoid_readonly(
+    deletion: Deletion,
+    new: &StoredImage,
+    previous: &StoredImage,
+    is_readonly: impl Fn(&StoredImage) -> bool,
+) -> Option<Deletion> {
+    let (chosen, other, other_deletion) = match deletion {
+        Deletion::New => (new, previous, Deletion::Previous),
+        Deletion::Previous => (previous, new, Deletion::New),
+    };
+    if !is_readonly(chosen) {
+        return Some(deletion);
+    }
+    // images older than --since are kept just like read-only ones
+    if is_readonly(other) || other.is_reference {
+        return None;
+    }
+    Some(other_deletion)
+}
+
+/// Check whether a file is inside one of the (canonicalized) `--readonly`
+/// directories.
+fn is_in_readonly_dir(path: &str, readonly_dirs: &[PathBuf]) -> bool {
+    let Ok(path) = fs::canonicalize(path) else {
+        return false;
+    };
+    readonly_dirs.iter().any(|dir| path.starts_with(dir))
+}
+
+fn main() {
+    // diagnostics for debugging a run, which are only shown with RUST_LOG set.
+    // results and anything meant for the user are still printed directly.
+    env_logger::init();
+    restore_sigpipe();
+    let args = parse_args();
+
+    let result = if let Some((a, b)) = &args.compare {
+        compare(&args, a, b)
+    } else if let Some((old, new)) = &args.compare_report {
+        compare_report(&args, old, new)
+    } else if args.selftest {
+        selftest::run(&args)
+    } else if args.per_dir {
+        run_per_dir(&args)
+    } else {
+        run(&args)
+    };
+    match result {
+        Ok(exit_code) => exit_code.exit(),
+        Err(err) => {
+            eprintln!("{} {err}", args.theme.err.paint("error:"));
+            match err {
+                ImdedupError::InvalidArgument(_) => ExitCode::InvalidArguments.exit(),
+                _ => ExitCode::Errors.exit(),
+            }
+        }
+    }
+}
+
+/// Rust ignores SIGPIPE, so writing to a closed pipe, like when the output is
+/// piped into `head`, is an error that makes `println!` panic. Going back to
+/// the default lets the process be stopped quietly instead, like other command
+/// line tools.
+fn restore_sigpipe() {
+    #[cfg(unix)]
+    // SAFETY: nothing else is changing signal handlers at startup
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+}
+
+/// Warn that `--delete` can't be undone, unless `--yes` was passed.
+fn warn_before_delete(args: &Args) {
+    if args.delete && !args.yes {
+        eprintln!(
+            "{} files will be deleted permanently as they're found, this can't be undone (pass --yes to hide this warning)",
+            args.theme.warning.paint("warning:")
+        );
+    }
+}
+
+/// Scan the files directly inside the directory, and then each subdirectory on
+/// its own, for `--per-dir`. Every scan starts with no hashes, so images are
+/// never matched across subdirectories, and each one prints its own summary.
+fn run_per_dir(args: &Args) -> Result<ExitCode, ImdedupError> {
+    let root = Path::new(args.path.as_deref().unwrap_or_default());
+    // a single file has nothing to be split up
+    if !root.is_dir() {
+        return run(args);
+    }
+    let read_dir_error = |err| ImdedupError::io(root, err);
+    let mut subdirs = Vec::new();
+    let mut has_files = false;
+    for entry in fs::read_dir(root).map_err(read_dir_error)? {
+        let entry = entry.map_err(read_dir_error)?;
+        let file_type = entry
+            .file_type()
+            .map_err(|err| ImdedupError::io(entry.path(), err))?;
+        if file_type.is_dir() && !should_skip_dir(&entry.file_name().to_string_lossy(), args) {
+            subdirs.push(entry.path());
+        } else if file_type.is_file() {
+            has_files = true;
+        }
+    }
+    subdirs.sort();
+
+    warn_before_delete(args);
+    // the warning was already printed once
+    let args = Args {
+        yes: true,
+        ..args.clone()
+    };
+
+    let mut exit_code = ExitCode::Clean;
+    let mut scan = |dir: &Path, recursive: bool| -> Result<(), ImdedupError> {
+        if !args.quiet {
+            println!("{}:", display_path(&dir.to_string_lossy(), &args));
+        }
+        let dir_exit_code = run(&Args {
+            path: Some(dir.to_string_lossy().to_string()),
+            recursive,
+            ..args.clone()
+        })?;
+        // the most severe outcome wins, like it does within a single scan
+        exit_code = cmp::max_by_key(exit_code, dir_exit_code, |code| *code as u8);
+        Ok(())
+    };
+    if has_files {
+        scan(root, false)?;
+    }
+    for subdir in subdirs {
+        scan(&subdir, true)?;
+    }
+    Ok(exit_code)
+}
+
+/// Hash two images and print whether they match, for `--compare`.
+fn compare(args: &Args, a: &str, b: &str) -> Result<ExitCode, ImdedupError> {
+    let theme = args.theme;
+    let hasher = build_hasher(args);
+    let hash_file = |path: &str| {
+        let source = Source::File(PathBuf::from(path));
+        match load_image(&source, args, None)? {
+            Some(LoadedImage { image, frames, .. }) => Ok((
+                hash_image(&hasher, image, args),
+                animation::signature(&hasher, frames, args),
+            )),
+            None => Err(ImdedupError::InvalidArgument(format!(
+                "{path} isn't a supported image"
+            ))),
+        }
+    };
+    let ((a_hash, a_signature), (b_hash, b_signature)) = (hash_file(a)?, hash_file(b)?);
+
+    let diff_bits = match_distance(
+        &a_hash,
+        a_signature.as_deref(),
+        &b_hash,
+        b_signature.as_deref(),
+        None,
+    );
+    let distance = if args.percent {
+        similarity_percent(diff_bits, a_hash.len() as u32 * 8)
+    } else {
+        format!("{diff_bits} bits")
+    };
+    if diff_bits == 0 {
+        println!("{} {a} == {b} ({distance})", theme.dup.paint("dup"));
+    } else if diff_bits <= SIMILARITY_THRESHOLD {
+        println!("{} {a} ~= {b} ({distance})", theme.sim.paint("sim"));
+    } else {
+        println!("{} {a} != {b} ({distance})", theme.uniq.paint("different"));
+    }
+
+    Ok(if diff_bits <= SIMILARITY_THRESHOLD {
+        ExitCode::Clean
+    } else {
+        ExitCode::CheckFailed
+    })
+}
+
+/// Print how the duplicates changed between the manifests of two scans, for
+/// `--compare-report`.
+fn compare_report(args: &Args, old: &str, new: &str) -> Result<ExitCode, ImdedupError> {
+    let theme = args.theme;
+    let changes = report::diff(
+        &report::read_manifest(Path::new(old))?,
+        &report::read_manifest(Path::new(new))?,
+    );
+    for (path, matching_path) in &changes.new_duplicates {
+        println!(
+            "{} {} ~= {}",
+            theme.dup.paint("new dup"),
+            display_path(path, args),
+            display_path(matching_path, args)
+        );
+    }
+    for (path, gone) in &changes.resolved_duplicates {
+        let how = if *gone { "gone" } else { "no longer matches" };
+        println!(
+            "{} {} ({how})",
+            theme.uniq.paint("resolved"),
+            display_path(path, args)
+        );
+    }
+    for path in &changes.new_uniques {
+        println!(
+            "{} {}",
+            theme.uniq.paint("new uniq"),
+            display_path(path, args)
+        );
+    }
+    println!(
+        "{} new duplicates, {} resolved, {} new unique files",
+        changes.new_duplicates.len(),
+        changes.resolved_duplicates.len(),
+        changes.new_uniques.len()
+    );
+
+    Ok(if args.fail_on_dup && !changes.new_duplicates.is_empty() {
+        ExitCode::CheckFailed
+    } else {
+        ExitCode::Clean
+    })
+}
+
+fn run(args: &Args) -> Result<ExitCode, ImdedupError> {
+    let theme = args.theme;
+
+    warn_before_delete(args);
+
+    let system_log = args
+        .syslog
+        .then(system_log::SystemLog::connect)
+        .transpose()?;
+    let hasher = build_hasher(args);
+    let io_limit = args.io_threads.map(Semaphore::new);
+    let readonly_dirs = args
+        .readonly_dirs
+        .iter()
+        .map(|dir| fs::canonicalize(dir).map_err(|err| ImdedupError::io(dir, err)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let blocklist = args
+        .blocklist
+        .as_deref()
+        .map(read_blocklist)
+        .transpose()?
+        .unwrap_or_default();
+
+    if args.tui && cfg!(not(feature = "tui")) {
+        return Err(ImdedupError::InvalidArgument(
+            "--tui requires imdedup to be built with the `tui` feature".to_string(),
+        ));
+    }
+
+    let mut file_paths = Vec::new();
+    if let Some(path) = &args.path {
+        collect_input(Path::new(path), args, &mut file_paths)?;
+    }
+
+    let mut sources = Vec::new();
+    let mut reference_sources = Vec::new();
+    for path in file_paths {
+        if let Some(since) = args.since {
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|err| ImdedupError::io(&path, err))?;
+            if modified < since {
+                reference_sources.push(Source::File(path));
+                continue;
+            }
+        }
+        sources.push(Source::File(path));
+    }
+    if let Some(url_list) = &args.url_list {
+        if cfg!(not(feature = "url")) {
+            return Err(ImdedupError::InvalidArgument(
+                "--url-list requires imdedup to be built with the `url` feature".to_string(),
+            ));
+        }
+        sources.extend(read_url_list(url_list)?.into_iter().map(Source::Url));
+    }
+    if let Some(archive_path) = &args.archive {
+        let archive = Arc::new(archive::Archive::open(Path::new(archive_path))?);
+        sources.extend(
+            archive
+                .file_names()?
+                .into_iter()
+                .map(|name| Source::ArchiveEntry {
+                    archive: archive.clone(),
+                    name,
+                }),
+        );
+    }
+
+    // only the final list of paths or the counts are printed to stdout with
+    // --uniques-only and --count-only, and nothing is with --quiet
+    let quiet = args.uniques_only || args.count_only || args.quiet;
+    // messages about the scan as a whole, rather than about single files
+    let print_notice = |message: &str| {
+        if let Some(system_log) = &system_log {
+            system_log.info(message);
+        }
+        if args.quiet {
+            // they're in the system log instead
+        } else if quiet {
+            eprintln!("{message}");
+        } else {
+            println!("{message}");
+        }
+    };
+
+    let checkpoint_path = args.checkpoint.as_deref().map(Path::new);
+    let mut counts = checkpoint::Counts::default();
+    let mut processed = HashSet::new();
+    let mut hashes = StoredImages::new();
+    let settings = hash_settings(args);
+    let checkpoint = checkpoint_path
+        .map(|checkpoint_path| checkpoint::load(checkpoint_path, &settings))
+        .transpose()?
+        .flatten();
+    if let Some(checkpoint) = checkpoint {
+        counts = checkpoint.counts;
+        processed = checkpoint.processed;
+        hashes = checkpoint.hashes;
+
+        // images that made it into the map were processed even if the checkpoint
+        // was saved before they were marked as processed, and skipping them is
+        // what stops an image from matching (and deleting) itself
+        let stored_paths = hashes
+            .values()
+            .flatten()
+            .map(|image| image.path.as_str())
+            .collect::<HashSet<_>>();
+        let source_count = sources.len();
+        sources.retain(|source| {
+            let path_string = source.path_string();
+            !processed.contains(&path_string) && !stored_paths.contains(path_string.as_str())
+        });
+        debug!(
+            "skipping {} files that the checkpoint already has",
+            source_count - sources.len()
+        );
+        print_notice(&format!(
+            "resuming from checkpoint, {} files already processed",
+            counts.processed
+        ));
+    }
+    // when each stored hash was last matched, for --max-index
+    let recency = args.max_index.map(|_| {
+        let mut recency = recency::Recency::default();
+        for hash in hashes.keys() {
+            recency.touch(hash);
+        }
+        Mutex::new(recency)
+    });
+    let hashes = Mutex::new(hashes);
+    // every hash, including exact duplicates that replace each other in `hashes`
+    let all_hashes = Mutex::new(Vec::new());
+    // directories that files were deleted from, for --prune-empty-dirs
+    let deleted_from = Mutex::new(HashSet::new());
+    // the path and hash of every file, for --duplicate-dirs
+    let scanned_files = Mutex::new(Vec::new());
+    // the hash and size of every file that hasn't been deleted, for --manifest
+    let manifest = Mutex::new(BTreeMap::new());
+    // lines for --pairs
+    let pairs = Mutex::new(Vec::new());
+    // every matching pair, to be grouped for --min-group-size
+    let group_matches = Mutex::new(Vec::new());
+    // and for --tui
+    #[cfg(feature = "tui")]
+    let review_matches = Mutex::new(Vec::new());
+    // how many images have matched each stored hash, including itself
+    let match_counts = Mutex::new(HashMap::<Vec<u8>, u64>::new());
+    let processed = Mutex::new(processed);
+    let last_checkpoint = Mutex::new(Instant::now());
+    let progress_path = args.progress_file.as_deref().map(Path::new);
+    let last_progress = Mutex::new(None::<Instant>);
+    let last_logged_progress = Mutex::new(Instant::now());
+
+    let file_count = sources.len() as u64 + counts.processed;
+
+    let dup_count = AtomicU64::new(counts.dup);
+    let sim_count = AtomicU64::new(counts.sim);
+    let uniq_count = AtomicU64::new(counts.uniq);
+    let blk_count = AtomicU64::new(counts.blk);
+    let err_count = AtomicU64::new(counts.err);
+
+    let processed_count = AtomicU64::new(counts.processed);
+    // unlike processed_count, this includes files that turned out not to be images
+    let scanned_count = AtomicU64::new(counts.processed);
+
+    if let Some(system_log) = &system_log {
+        system_log.info(&format!("scanning {} files", sources.len()));
+    }
+
+    let save_checkpoint = |checkpoint_path: &Path| {
+        let hashes = hashes.lock();
+        let processed = processed.lock();
+        let counts = checkpoint::Counts {
+            processed: processed_count.load(atomic::Ordering::Relaxed),
+            dup: dup_count.load(atomic::Ordering::Relaxed),
+            sim: sim_count.load(atomic::Ordering::Relaxed),
+            uniq: uniq_count.load(atomic::Ordering::Relaxed),
+            blk: blk_count.load(atomic::Ordering::Relaxed),
+            err: err_count.load(atomic::Ordering::Relaxed),
+        };
+        debug!(
+            "saving checkpoint with {} processed files",
+            counts.processed
+        );
+        checkpoint::save(checkpoint_path, &settings, counts, &processed, &hashes)
+    };
+
+    if !reference_sources.is_empty() {
+        print_notice(&format!(
+            "comparing against {} files modified before --since",
+            reference_sources.len()
+        ));
+    }
+    // older files aren't part of the scan, so they aren't reported or counted
+    reference_sources.into_par_iter().for_each(|source| {
+        let Source::File(path) = &source else {
+            return;
+        };
+        let Ok(Some(LoadedImage {
+            image,
+            dimensions,
+            file_size,
+            created_at,
+            frames,
+        })) = load_image(&source, args, io_limit.as_ref())
+        else {
+            return;
+        };
+        let hash = hash_image(&hasher, image, args);
+        let signature = animation::signature(&hasher, frames, args);
+        let path_string = path.to_string_lossy().to_string();
+        if args.manifest.is_some() {
+            manifest
+                .lock()
+                .insert(path_string.clone(), (hash.clone(), file_size));
+        }
+        if let Some(recency) = &recency {
+            recency.lock().touch(&hash);
+        }
+        store_image(
+            &mut hashes.lock(),
+            hash,
+            StoredImage {
+                path: path_string,
+                file_size,
+                created_at,
+                dimensions,
+                is_url: false,
+                is_reference: true,
+                matched: false,
+                signature,
+            },
+        );
+    });
+
+    let report_error = |err: ImdedupError| {
+        debug!("{err:?}");
+        let i = processed_count.fetch_add(1, atomic::Ordering::Relaxed);
+        if quiet {
+            eprintln!("{} {err}", theme.err.paint("error:"));
+        } else {
+            println!("{}/{file_count} {} {err}", i + 1, theme.err.paint("err"));
+        }
+        err_count.fetch_add(1, atomic::Ordering::Relaxed);
+    };
+
+    let note_deleted = |path: &str| {
+        if args.manifest.is_some() {
+            manifest.lock().remove(path);
+        }
+        if let (true, Some(parent)) = (args.prune_empty_dirs, Path::new(path).parent()) {
+            deleted_from.lock().insert(parent.to_owned());
+        }
+    };
+
+    // every deletion goes through here, so a blocklisted file gets the same
+    // checks and output as the worse copy of a pair. returns whether the file
+    // was deleted.
+    let delete_image = |i: u64, hash_hex: &str, path: &str, kept: Option<&StoredImage>| {
+        if is_in_readonly_dir(path, &readonly_dirs) {
+            eprintln!(
+                "{} not deleting {}, it's in a --readonly directory",
+                theme.warning.paint("warning:"),
+                display_path(path, args)
+            );
+            return false;
+        }
+        if let Some(kept) = kept.filter(|kept| !kept.is_url) {
+            if args.same_device_only {
+                match same_device(path, &kept.path) {
+                    Ok(true) => {}
+                    // nothing went wrong with the scan, so it isn't counted
+                    // as an error
+                    Ok(false) => {
+                        let err = ImdedupError::CrossDevice {
+                            path: display_path(path, args).into_owned(),
+                            kept: kept.display_path(args).into_owned(),
+                        };
+                        eprintln!(
+                            "{} {err}, so it isn't being deleted",
+                            theme.warning.paint("warning:")
+                        );
+                        return false;
+                    }
+                    Err(err) => {
+                        report_error(err);
+                        return false;
+                    }
+                }
+            }
+            // the copy being kept might have been moved or deleted by
+            // something else since it was scanned
+            if let Err(err) = check_readable(&kept.path) {
+                eprintln!(
+                    "{} not deleting {}, the copy being kept can't be read: {err}",
+                    theme.warning.paint("warning:"),
+                    display_path(path, args),
+                );
+                return false;
+            }
+        }
+
+        if let Err(err) = fs::remove_file(path) {
+            report_error(ImdedupError::io(path, err));
+            return false;
+        }
+        // so it's clear which file of each pair is left if the run is
+        // interrupted
+        if !args.quiet {
+            let kept_display = kept
+                .map(|kept| format!(", kept {}", kept.display_path(args)))
+                .unwrap_or_default();
+            println!(
+                "{}/{file_count} {} {} {}{kept_display}",
+                i + 1,
+                theme.hash.paint(hash_hex),
+                theme.dup.paint("del"),
+                display_path(path, args),
+            );
+        }
+        if let Some(system_log) = &system_log {
+            system_log.info(&match kept {
+                Some(kept) => format!("deleted {path}, kept {}", kept.path),
+                None => format!("deleted {path}, it's blocklisted"),
+            });
+        }
+        note_deleted(path);
+        true
+    };
+
+    let hash_source = |source: &Source| {
+        if let Some(mode) = args.exact {
+            return match exact::hash(source, mode, args, io_limit.as_ref()) {
+                Ok(hashed) => hashed,
+                Err(err) => {
+                    report_error(err);
+                    None
+                }
+            };
+        }
+        let LoadedImage {
+            image,
+            dimensions,
+            file_size,
+            created_at,
+            frames,
+        } = match load_image(source, args, io_limit.as_ref()) {
+            Ok(Some(loaded)) => loaded,
+            Ok(None) => return None,
+            Err(err) => {
+                report_error(err);
+                return None;
+            }
+        };
+        let hash = hash_image(&hasher, image, args);
+        trace!("hashed {} as {}", source.path_string(), hex::encode(&hash));
+        Some(HashedImage {
+            hash,
+            signature: animation::signature(&hasher, frames, args),
+            dimensions,
+            file_size,
+            created_at,
+        })
+    };
+
+    let scan = |source: &Source, hashed: HashedImage, weights: Option<&[f64]>| {
+        let path_string = source.path_string();
+        let is_url = matches!(source, Source::Url(_));
+        let HashedImage {
+            hash,
+            signature,
+            dimensions,
+            file_size,
+            created_at,
+        } = hashed;
+        if args.manifest.is_some() && !is_url {
+            manifest
+                .lock()
+                .insert(path_string.clone(), (hash.clone(), file_size));
+        }
+
+        let blocked = blocklist
+            .iter()
+            .any(|blocked_hash| hamming_distance(&hash, blocked_hash) <= SIMILARITY_THRESHOLD);
+        if blocked {
+            let hash_hex = hex::encode(&hash);
+            let i = processed_count.fetch_add(1, atomic::Ordering::Relaxed);
+            if !quiet {
+                let shown_path = if is_url {
+                    Cow::Borrowed(path_string.as_str())
+                } else {
+                    display_path(&path_string, args)
+                };
+                println!(
+                    "{}/{file_count} {} {} {shown_path}",
+                    i + 1,
+                    theme.hash.paint(&hash_hex),
+                    theme.blk.paint("blk")
+                );
+            }
+            blk_count.fetch_add(1, atomic::Ordering::Relaxed);
+            if args.delete && !is_url {
+                delete_image(i, &hash_hex, &path_string, None);
+            }
+            return;
+        }
+
+        if args.histogram {
+            all_hashes.lock().push(hash.clone());
+        }
+        if args.duplicate_dirs && !is_url {
+            scanned_files
+                .lock()
+                .push((path_string.clone(), hash.clone(), signature.clone()));
+        }
+
+        let mut stored_image = StoredImage {
+            path: path_string,
+            file_size,
+            created_at,
+            dimensions,
+            is_url,
+            is_reference: false,
+            matched: false,
+            signature,
+        };
+
+        let mut hashes = hashes.lock();
+        // every match is needed to know which images are really unique. an exact
+        // match's similar images were already compared against the stored copy
+        // with the same hash, so they don't need to be searched for again.
+        let signature = stored_image.signature.as_deref();
+        let (dup_of, sim_to) = if args.exact.is_some() {
+            // exact hashes are either the same or unrelated, so there's
+            // nothing similar to look for
+            let dup_of = hashes
+                .get_key_value(&hash)
+                .and_then(|(hash, images)| Some((hash, images.first()?)));
+            (dup_of, Vec::new())
+        } else {
+            find_matches(
+                &hashes,
+                &hash,
+                signature,
+                weights,
+                args.all_matches
+                    || args.uniques_only
+                    || args.pairs.is_some()
+                    || args.tui
+                    || args.min_group_size.is_some(),
+                args.all_matches
+                    || args.pairs.is_some()
+                    || args.tui
+                    || args.min_group_size.is_some(),
+            )
+        };
+        if args.pairs.is_some() {
+            let mut pairs = pairs.lock();
+            for (other_hash, other_image) in dup_of.iter().chain(sim_to.iter()) {
+                let distance = match_distance(
+                    &hash,
+                    signature,
+                    other_hash,
+                    other_image.signature.as_deref(),
+                    weights,
+                );
+                let status = if distance == 0 { "dup" } else { "sim" };
+                pairs.push(format!(
+                    "{}\t{}\t{distance}\t{status}\n",
+                    escape_tsv(&stored_image.display_path(args)),
+                    escape_tsv(&other_image.display_path(args))
+                ));
+            }
+        }
+        if args.min_group_size.is_some() {
+            let mut group_matches = group_matches.lock();
+            for (_, other_image) in dup_of.iter().chain(sim_to.iter()) {
+                group_matches.push((
+                    stored_image.display_path(args).into_owned(),
+                    other_image.display_path(args).into_owned(),
+                ));
+            }
+        }
+        #[cfg(feature = "tui")]
+        if args.tui {
+            let review_file = |hash: &Vec<u8>, image: &StoredImage| tui::File {
+                path: image.path.clone(),
+                hash: hash.clone(),
+                file_size: image.file_size,
+                dimensions: image.dimensions,
+                deletable: !image.is_url
+                    && !image.is_reference
+                    && !is_in_readonly_dir(&image.path, &readonly_dirs),
+            };
+            let mut review_matches = review_matches.lock();
+            for (other_hash, other_image) in dup_of.iter().chain(sim_to.iter()) {
+                review_matches.push((
+                    review_file(&hash, &stored_image),
+                    review_file(other_hash, other_image),
+                ));
+            }
+        }
+        // there can be more than one image with a hash, so they're found again
+        // by their path too
+        let matched_images = dup_of
+            .iter()
+            .chain(sim_to.iter())
+            .map(|(hash, image)| ((*hash).clone(), image.path.clone()))
+            .collect::<Vec<_>>();
+        stored_image.matched = !matched_images.is_empty();
+
+        let path_string = stored_image.display_path(args);
+        let total_bits = hash.len() as u32 * 8;
+        // more matches than the first may have been found for other options
+        let shown_sim_to = if args.all_matches { sim_to.len() } else { 1 };
+        let sim_to_paths = sim_to
+            .iter()
+            .take(shown_sim_to)
+            .map(|(sim_hash, sim_to)| {
+                if args.percent {
+                    let diff_bits = match_distance(
+                        &hash,
+                        signature,
+                        sim_hash,
+                        sim_to.signature.as_deref(),
+                        weights,
+                    );
+                    format!(
+                        "{} ({})",
+                        sim_to.display_path(args),
+                        similarity_percent(diff_bits, total_bits)
+                    )
+                } else {
+                    sim_to.display_path(args).into_owned()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        // a thumbnail is reported as one instead of as a dup or sim, unless
+        // every match is being listed
+        let thumbnail_pair = dup_of
+            .or(sim_to.first().copied())
+            .map(|(_, other_image)| other_image)
+            .filter(|_| args.detect_thumbnails && !args.all_matches)
+            .and_then(|other_image| {
+                if is_thumbnail_of(&stored_image, other_image) {
+                    Some((path_string.clone(), other_image.display_path(args)))
+                } else if is_thumbnail_of(other_image, &stored_image) {
+                    Some((other_image.display_path(args), path_string.clone()))
+                } else {
+                    None
+                }
+            });
+        // likewise for a resize, though a thumbnail is reported as a thumbnail
+        let resize_pair = dup_of
+            .or(sim_to.first().copied())
+            .map(|(_, other_image)| other_image)
+            .filter(|_| args.resize_match && !args.all_matches)
+            .and_then(|other_image| {
+                if is_resize_of(&stored_image, other_image) {
+                    Some((path_string.clone(), other_image.display_path(args)))
+                } else if is_resize_of(other_image, &stored_image) {
+                    Some((other_image.display_path(args), path_string.clone()))
+                } else {
+                    None
+                }
+            });
+        let display = if let Some((thumbnail, original)) = thumbnail_pair {
+            format!(
+                "{} {thumbnail} thumbnail of {original}",
+                theme.sim.paint("thumb")
+            )
+        } else if let Some((resize, original)) = resize_pair {
+            format!(
+                "{} {resize} resize of {original}",
+                theme.sim.paint("resize")
+            )
+        } else if let Some((_, dup_of)) = dup_of {
+            let dup_of_path = dup_of.display_path(args);
+            if args.all_matches && !sim_to.is_empty() {
+                format!(
+                    "{} {path_string} == {dup_of_path} ~= {sim_to_paths}",
+                    theme.dup.paint("dup")
+                )
+            } else {
+                format!("{} {path_string} == {dup_of_path}", theme.dup.paint("dup"))
+            }
+        } else if !sim_to.is_empty() {
+            format!("{} {path_string} ~= {sim_to_paths}", theme.sim.paint("sim"))
+        } else {
+            "".to_string()
+        };
+        let name_relation = dup_of
+            .or(sim_to.first().copied())
+            .map(|(_, other_image)| other_image)
+            .filter(|_| args.filename_hint && !args.all_matches)
+            .and_then(|other_image| {
+                if is_named_copy_of(&stored_image, other_image) {
+                    Some("copy")
+                } else if is_named_copy_of(other_image, &stored_image) {
+                    Some("original")
+                } else {
+                    None
+                }
+            });
+        let display = match name_relation {
+            Some(relation) => format!("{display} ({relation} by name)"),
+            None => display,
+        };
+        // deleting one of them wouldn't free any space
+        let already_linked = dup_of
+            .or(sim_to.first().copied())
+            .map(|(_, other_image)| other_image)
+            .filter(|other_image| !stored_image.is_url && !other_image.is_url)
+            .is_some_and(|other_image| same_inode(&stored_image.path, &other_image.path));
+        let display = if already_linked {
+            format!("{display} (already linked)")
+        } else {
+            display
+        };
+        // the matches are listed in their groups after the scan instead
+        let display = if args.min_group_size.is_some() {
+            String::new()
+        } else {
+            display
+        };
+
+        // these are only reported, --delete never acts on them
+        let near_misses = match args.near_miss {
+            Some(bits) => find_near_misses(&hashes, &hash, signature, weights, bits),
+            None => Vec::new(),
+        };
+
+        let hash_hex = hex::encode(&hash);
+
+        let i = processed_count.fetch_add(1, atomic::Ordering::Relaxed);
+        if !quiet {
+            print!(
+                "{}/{file_count} {} {display}\r",
+                i + 1,
+                theme.hash.paint(&hash_hex)
+            );
+            // this only fails if stdout was closed, see restore_sigpipe
+            let _ = io::stdout().flush();
+            if !display.is_empty() {
+                println!();
+            }
+            for &(near_image, distance) in &near_misses {
+                let distance = if args.percent {
+                    similarity_percent(distance, total_bits)
+                } else {
+                    format!("{distance} bits")
+                };
+                println!(
+                    "{}/{file_count} {} {} {path_string} ~? {} ({distance})",
+                    i + 1,
+                    theme.hash.paint(&hash_hex),
+                    theme.near.paint("near"),
+                    near_image.display_path(args)
+                );
+            }
+        }
+
+        if dup_of.is_some() {
+            dup_count.fetch_add(1, atomic::Ordering::Relaxed);
+        } else if !sim_to.is_empty() {
+            sim_count.fetch_add(1, atomic::Ordering::Relaxed);
+        } else {
+            uniq_count.fetch_add(1, atomic::Ordering::Relaxed);
+        }
+
+        let suspicious = match dup_of.or(sim_to.first().copied()) {
+            Some((previous_hash, _)) => {
+                let mut match_counts = match_counts.lock();
+                let count = match_counts.entry(previous_hash.clone()).or_insert(1);
+                *count += 1;
+                if args.delete && !args.delete_suspicious && *count == args.suspicious_size + 1 {
+                    eprintln!(
+                        "{} {} has matched more than {} images, which usually means they're blank or placeholder images rather than copies, so no more of them will be deleted (pass --delete-suspicious to delete them anyway)",
+                        theme.warning.paint("warning:"),
+                        hex::encode(previous_hash),
+                        args.suspicious_size
+                    );
+                }
+                let degenerate = is_degenerate_hash(previous_hash);
+                if args.delete && !args.delete_suspicious && *count == 2 && degenerate {
+                    eprintln!(
+                        "{} {} is the hash of a blank or solid-color image, which all match each other, so its matches won't be deleted (pass --delete-suspicious to delete them anyway)",
+                        theme.warning.paint("warning:"),
+                        hex::encode(previous_hash)
+                    );
+                }
+                *count > args.suspicious_size || degenerate
+            }
+            None => false,
+        };
+
+        let mut should_insert = true;
+        let mut image_to_remove = None;
+
+        if args.delete && suspicious && !args.delete_suspicious {
+            if let Some((previous_hash, _)) = dup_of.or(sim_to.first().copied()) {
+                debug!(
+                    "not deleting {path_string} or its match, {} is suspicious",
+                    hex::encode(previous_hash)
+                );
+            }
+        }
+
+        if args.delete && (!suspicious || args.delete_suspicious) {
+            if let Some((previous_hash, previous_stored_image)) = dup_of.or(sim_to.first().copied())
+            {
+                let mut deletion =
+                    choose_to_delete(&stored_image, previous_stored_image, args.keep);
+                // what decided which image is deleted
+                let mut reason = if previous_stored_image.is_reference {
+                    "--since"
+                } else {
+                    "--keep"
+                };
+                if args.filename_hint {
+                    if let Some(filename_deletion) =
+                        filename_deletion(&stored_image, previous_stored_image)
+                    {
+                        deletion = filename_deletion;
+                        reason = "--filename-hint";
+                    }
+                }
+                // so is a lower resolution copy
+                if args.resize_match {
+                    if let Some(resize_deletion) =
+                        resize_deletion(&stored_image, previous_stored_image)
+                    {
+                        deletion = resize_deletion;
+                        reason = "--resize-match";
+                    }
+                }
+                // a thumbnail is the worse copy whatever it's called
+                if args.detect_thumbnails {
+                    if let Some(thumbnail_deletion) =
+                        thumbnail_deletion(&stored_image, previous_stored_image)
+                    {
+                        deletion = thumbnail_deletion;
+                        reason = "--detect-thumbnails";
+                    }
+                }
+                let mut can_delete = true;
+                if !readonly_dirs.is_empty() {
+                    let is_readonly =
+                        |image: &StoredImage| is_in_readonly_dir(&image.path, &readonly_dirs);
+                    match avoid_readonly(
+                        deletion,
+                        &stored_image,
+                        previous_stored_image,
+                        is_readonly,
+                    ) {
+                        Some(allowed_deletion) => {
+                            if allowed_deletion != deletion {
+                                reason = "--readonly";
+                            }
+                            deletion = allowed_deletion;
+                        }
+                        None => {
+                            eprintln!(
+                                "{} not deleting {} or {}, since neither is allowed to be deleted",
+                                theme.warning.paint("warning:"),
+                                stored_image.display_path(args),
+                                previous_stored_image.display_path(args)
+                            );
+                            can_delete = false;
+                        }
+                    }
+                }
+                let (image_to_delete, image_to_keep) = match deletion {
+                    Deletion::New => (&stored_image, previous_stored_image),
+                    Deletion::Previous => (previous_stored_image, &stored_image),
+                };
+                debug!(
+                    "chose to delete {} and keep {} because of {reason}",
+                    image_to_delete.path, image_to_keep.path
+                );
+                // images from urls are only ever compared against, not deleted
+                can_delete &= !image_to_delete.is_url;
+                if already_linked {
+                    debug!(
+                        "not deleting {}, it's a hard link to {}",
+                        image_to_delete.path, image_to_keep.path
+                    );
+                    can_delete = false;
+                }
+
+                let same_size = stored_image.file_size == previous_stored_image.file_size;
+                let both_files = !stored_image.is_url && !previous_stored_image.is_url;
+                if can_delete && args.verify_bytes && dup_of.is_some() && same_size && both_files {
+                    match same_contents(&stored_image.path, &previous_stored_image.path) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            eprintln!(
+                                "{} not deleting {}, its contents differ from {}",
+                                theme.warning.paint("warning:"),
+                                image_to_delete.display_path(args),
+                                image_to_keep.display_path(args)
+                            );
+                            can_delete = false;
+                        }
+                        Err(err) => {
+                            report_error(err);
+                            can_delete = false;
+                        }
+                    }
+                }
+
+                if can_delete
+                    && delete_image(i, &hash_hex, &image_to_delete.path, Some(image_to_keep))
+                {
+                    match deletion {
+                        Deletion::New => should_insert = false,
+                        Deletion::Previous => {
+                            image_to_remove =
+                                Some((previous_hash.clone(), previous_stored_image.path.clone()))
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut recency = recency.as_ref().map(|recency| recency.lock());
+        for (matched_hash, matched_path) in matched_images {
+            let matched_image = hashes
+                .get_mut(&matched_hash)
+                .and_then(|images| images.iter_mut().find(|image| image.path == matched_path));
+            if let Some(matched_image) = matched_image {
+                matched_image.matched = true;
+                if let Some(recency) = &mut recency {
+                    recency.touch(&matched_hash);
+                }
+            }
+        }
+        // don't keep comparing against images that were deleted
+        if let Some((hash_to_remove, path_to_remove)) = image_to_remove {
+            if let Some(images) = hashes.get_mut(&hash_to_remove) {
+                images.retain(|image| image.path != path_to_remove);
+                if images.is_empty() {
+                    hashes.remove(&hash_to_remove);
+                    if let Some(recency) = &mut recency {
+                        recency.remove(&hash_to_remove);
+                    }
+                }
+            }
+        }
+        if should_insert {
+            if let Some(recency) = &mut recency {
+                recency.touch(&hash);
+            }
+            store_image(&mut hashes, hash, stored_image);
+        }
+        if let (Some(recency), Some(max_index)) = (&mut recency, args.max_index) {
+            while hashes.len() > max_index {
+                let Some(oldest_hash) = recency.pop_oldest() else {
+                    break;
+                };
+                for forgotten in hashes.remove(&oldest_hash).into_iter().flatten() {
+                    trace!("forgetting {} to stay within --max-index", forgotten.path);
+                }
+            }
+        }
+    };
+
+    let finish_source = |source: &Source| {
+        let scanned = scanned_count.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+        if let Some(progress_path) = progress_path {
+            if let Some(mut last_progress) = last_progress.try_lock() {
+                if last_progress
+                    .is_none_or(|last_progress| last_progress.elapsed() >= PROGRESS_INTERVAL)
+                {
+                    if let Err(err) = write_progress(progress_path, scanned, file_count) {
+                        eprintln!(
+                            "{} failed to write progress: {err}",
+                            theme.warning.paint("warning:")
+                        );
+                    }
+                    *last_progress = Some(Instant::now());
+                }
+            }
+        }
+        if let Some(system_log) = &system_log {
+            if let Some(mut last_logged_progress) = last_logged_progress.try_lock() {
+                if last_logged_progress.elapsed() >= system_log::PROGRESS_INTERVAL {
+                    system_log.info(&format!(
+                        "scanned {scanned}/{file_count} files, {} dup, {} sim, {} uniq",
+                        dup_count.load(atomic::Ordering::Relaxed),
+                        sim_count.load(atomic::Ordering::Relaxed),
+                        uniq_count.load(atomic::Ordering::Relaxed)
+                    ));
+                    *last_logged_progress = Instant::now();
+                }
+            }
+        }
+
+        let Some(checkpoint_path) = checkpoint_path else {
+            return;
+        };
+        processed.lock().insert(source.path_string());
+        // only one thread needs to save the checkpoint, the rest can move on
+        if let Some(mut last_checkpoint) = last_checkpoint.try_lock() {
+            if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                if let Err(err) = save_checkpoint(checkpoint_path) {
+                    eprintln!(
+                        "{} failed to save checkpoint: {err}",
+                        theme.warning.paint("warning:")
+                    );
+                }
+                *last_checkpoint = Instant::now();
+            }
+        }
+    };
+
+    let weights = if args.weighted_distance || args.order_by.is_some() {
+        // the weights are learned from every image, and the order is only
+        // known once every image has its key, so they all have to be hashed
+        // before any of them can be compared
+        let hashed = sources.par_iter().map(&hash_source).collect::<Vec<_>>();
+        let weights = args.weighted_distance.then(|| {
+            // images that are only compared against, like ones from before
+            // --since, are part of the set too
+            let stored_hashes = hashes.lock().keys().cloned().collect::<Vec<_>>();
+            let weights = weights::bit_weights(
+                hashed
+                    .iter()
+                    .flatten()
+                    .map(|image| image.hash.as_slice())
+                    .chain(stored_hashes.iter().map(Vec::as_slice)),
+            );
+            debug!("bit weights: {weights:.2?}");
+            weights
+        });
+        let scan_hashed = |(source, hashed): (Source, Option<HashedImage>)| {
+            if let Some(hashed) = hashed {
+                scan(&source, hashed, weights.as_deref());
+            }
+            finish_source(&source);
+        };
+        let mut hashed_sources = sources.into_iter().zip(hashed).collect::<Vec<_>>();
+        if let Some(order_by) = args.order_by {
+            // comparing one image at a time is what makes the first image of
+            // each group, which the rest are reported against, the same every
+            // time. it's cheap next to hashing.
+            hashed_sources.sort_by(|a, b| scan_order(order_by, a, b));
+            hashed_sources.into_iter().for_each(scan_hashed);
+        } else {
+            hashed_sources.into_par_iter().for_each(scan_hashed);
+        }
+        weights
+    } else {
+        sources.into_par_iter().for_each(|source| {
+            if let Some(hashed) = hash_source(&source) {
+                scan(&source, hashed, None);
+            }
+            finish_source(&source);
+        });
+        None
+    };
+
+    if let Some(checkpoint_path) = checkpoint_path {
+        save_checkpoint(checkpoint_path)?;
+    }
+    if let Some(progress_path) = progress_path {
+        write_progress(progress_path, file_count, file_count)?;
+    }
+
+    let mut suspicious_hashes = match_counts
+        .into_inner()
+        .into_iter()
+        .filter(|(hash, count)| *count > args.suspicious_size || is_degenerate_hash(hash))
+        .collect::<Vec<_>>();
+    suspicious_hashes.sort_by(|(a_hash, a_count), (b_hash, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_hash.cmp(b_hash))
+    });
+    if !suspicious_hashes.is_empty() && !quiet {
+        println!("suspicious hashes, which might be blank or placeholder images:");
+        for (hash, count) in suspicious_hashes {
+            println!(
+                "{} matched {count} images",
+                theme.hash.paint(hex::encode(hash))
+            );
+        }
+    }
+
+    #[cfg(feature = "tui")]
+    if args.tui {
+        let groups = groups::groups(review_matches.into_inner(), |file| &file.path);
+        for (file, kept) in tui::review(groups)? {
+            // the kept file is found again by its path, like a match is
+            let hashes = hashes.lock();
+            let kept = hashes
+                .get(&kept.hash)
+                .and_then(|images| images.iter().find(|image| image.path == kept.path));
+            delete_image(file_count - 1, &hex::encode(&file.hash), &file.path, kept);
+        }
+    }
+
+    if let Some(min_size) = args.min_group_size {
+        // the summary still counts every match, only the listing is filtered
+        for group in groups::groups(group_matches.into_inner(), |path| path) {
+            if group.len() < min_size {
+                continue;
+            }
+            println!("{} of {} images:", theme.dup.paint("group"), group.len());
+            for path in group {
+                println!("  {path}");
+            }
+        }
+    }
+
+    let dup_count = dup_count.load(atomic::Ordering::Relaxed);
+    let sim_count = sim_count.load(atomic::Ordering::Relaxed);
+    let uniq_count = uniq_count.load(atomic::Ordering::Relaxed);
+    let blk_count = blk_count.load(atomic::Ordering::Relaxed);
+    let err_count = err_count.load(atomic::Ordering::Relaxed);
+
+    let blk_display = if args.blocklist.is_some() {
+        format!(", {blk_count} {}", theme.blk.paint("blk"))
+    } else {
+        "".to_string()
+    };
+    let err_display = if err_count > 0 {
+        format!(", {err_count} {}", theme.err.paint("err"))
+    } else {
+        "".to_string()
+    };
+
+    if args.uniques_only {
+        let hashes = hashes.lock();
+        let mut unique_paths = hashes
+            .values()
+            .flatten()
+            .filter(|image| !image.matched && !image.is_reference)
+            .map(|image| image.display_path(args))
+            .collect::<Vec<_>>();
+        unique_paths.sort();
+        for path in unique_paths {
+            println!("{path}");
+        }
+    } else if args.count_only {
+        println!("{dup_count} {sim_count} {uniq_count}");
+    } else if !args.quiet {
+        // extra spaces at the end to remove any possible leftover characters :)
+        println!(
+            "{dup_count} {}, {sim_count} {}, {uniq_count} {}{blk_display}{err_display}        ",
+            theme.dup.paint("dup"),
+            theme.sim.paint("sim"),
+            theme.uniq.paint("uniq"),
+        );
+    }
+
+    if let Some(system_log) = &system_log {
+        let mut summary = format!("finished: {dup_count} dup, {sim_count} sim, {uniq_count} uniq");
+        if args.blocklist.is_some() {
+            summary += &format!(", {blk_count} blk");
+        }
+        if err_count > 0 {
+            summary += &format!(", {err_count} err");
+        }
+        system_log.info(&summary);
+    }
+
+    if args.histogram {
+        print_histogram(&nearest_distance_histogram(
+            &all_hashes.lock(),
+            weights.as_deref(),
+        ));
+    }
+
+    if args.duplicate_dirs {
+        let root = Path::new(args.path.as_deref().unwrap_or_default());
+        let duplicates = dirs::duplicate_dirs(&scanned_files.lock(), root, weights.as_deref());
+        println!("duplicate directories: {}", duplicates.len());
+        for dirs in duplicates {
+            let (kept, redundant) = dirs.split_first().unwrap();
+            for dir in redundant {
+                println!(
+                    "{} duplicates {}",
+                    display_path(&dir.to_string_lossy(), args),
+                    display_path(&kept.to_string_lossy(), args)
+                );
+            }
+        }
+    }
+
+    if let Some(manifest_path) = &args.manifest {
+        write_manifest(Path::new(manifest_path), &manifest.lock())?;
+    }
+
+    if let Some(pairs_path) = &args.pairs {
+        let contents = "a\tb\tdistance\tstatus\n".to_string() + &pairs.lock().concat();
+        fs::write(pairs_path, contents).map_err(|err| ImdedupError::io(pairs_path, err))?;
+    }
+
+    if let Some(dir) = &args.copy_uniques_to {
+        let hashes = hashes.lock();
+        let images = copy::representatives(&hashes, args.keep, weights.as_deref());
+        let copied = copy::copy_to(Path::new(dir), &images)?;
+        print_notice(&format!("copied {copied} images to {dir}"));
+    }
+
+    if let (true, Some(path)) = (args.prune_empty_dirs, &args.path) {
+        let pruned = prune_empty_dirs(deleted_from.into_inner(), Path::new(path));
+        print_notice(&format!("pruned {pruned} empty directories"));
+    }
+
+    let mut broken_count = 0;
+    if let (true, Some(path)) = (args.verify, &args.path) {
+        let report = verify::verify(args, &hasher, Path::new(path))?;
+        for (a, b, diff_bits) in &report.leftovers {
+            println!(
+                "{} {} still matches {} ({diff_bits} bits)",
+                theme.warning.paint("leftover:"),
+                display_path(&b.to_string_lossy(), args),
+                display_path(&a.to_string_lossy(), args)
+            );
+        }
+        for err in &report.broken {
+            println!("{} {err}", theme.err.paint("broken:"));
+        }
+        println!(
+            "verified {} images: {} matching pairs left, {} can't be opened",
+            report.checked,
+            report.leftovers.len(),
+            report.broken.len()
+        );
+        broken_count = report.broken.len();
+    }
+
+    // errors are the most important to surface, since they mean the scan may
+    // have missed some duplicates
+    Ok(if err_count > 0 || broken_count > 0 {
+        ExitCode::Errors
+    } else if blk_count > 0 || (args.fail_on_dup && dup_count + sim_count > 0) {
+        ExitCode::CheckFailed
+    } else {
+        ExitCode::Clean
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn stored_image(file_size: u64, created_at: u64) -> StoredImage {
+        StoredImage {
+            path: String::new(),
+            file_size,
+            created_at: SystemTime::UNIX_EPOCH + Duration::from_secs(created_at),
+            dimensions: (0, 0),
+            is_url: false,
+            is_reference: false,
+            matched: false,
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn splits_words_like_a_shell() {
+        assert_eq!(
+            split_words("  --no-color   --skip-dir  cache ").unwrap(),
+            ["--no-color", "--skip-dir", "cache"]
+        );
+        assert_eq!(
+            split_words(r#"--skip-dir 'my photos' --readonly "a \"b\"" c\ d ''"#).unwrap(),
+            [
+                "--skip-dir",
+                "my photos",
+                "--readonly",
+                "a \"b\"",
+                "c d",
+                ""
+            ]
+        );
+        assert_eq!(split_words("").unwrap(), Vec::<String>::new());
+        assert!(split_words("'unterminated").is_err());
+        assert!(split_words("\"unterminated").is_err());
+        assert!(split_words("trailing\\").is_err());
+    }
+
+    #[test]
+    fn hamming_distance_counts_differing_bits() {
+        assert_eq!(hamming_distance(&[0, 0], &[0, 0]), 0);
+        assert_eq!(hamming_distance(&[0b1010, 0], &[0b0101, 0]), 4);
+        assert_eq!(hamming_distance(&[0xff, 0xff], &[0, 0]), 16);
+        assert_eq!(hamming_distance(&[0x80, 0x01], &[0, 0]), 2);
+        // whole words and a tail
+        assert_eq!(hamming_distance(&[0xff; 8], &[0; 8]), 64);
+        assert_eq!(hamming_distance(&[0x0f; 11], &[0; 11]), 44);
+    }
+
+    #[test]
+    fn degenerate_hashes_are_nearly_uniform() {
+        assert!(is_degenerate_hash(&[0; 8]));
+        assert!(is_degenerate_hash(&[0xff; 8]));
+        assert!(is_degenerate_hash(&[0x01, 0, 0, 0, 0, 0, 0, 0x80]));
+        assert!(!is_degenerate_hash(&[0x07, 0, 0, 0, 0, 0, 0, 0]));
+        assert!(!is_degenerate_hash(&[0x5a; 8]));
+    }
+
+    #[test]
+    fn finds_all_similar_images() {
+        let mut hashes = BTreeMap::new();
+        hashes.insert(vec![0b0000_0000], vec![stored_image(0, 0)]);
+        hashes.insert(vec![0b0000_0001], vec![stored_image(0, 0)]);
+        hashes.insert(vec![0b0000_0011], vec![stored_image(0, 0)]);
+        hashes.insert(vec![0b1111_1111], vec![stored_image(0, 0)]);
+
+        let similar = find_similar(&hashes, &[0b0000_0001], None, None, true);
+        let similar_hashes = similar.iter().map(|(hash, _)| hash[0]).collect::<Vec<_>>();
+        assert_eq!(similar_hashes, [0b0000_0000, 0b0000_0011]);
+
+        assert_eq!(
+            find_similar(&hashes, &[0b0000_0001], None, None, false).len(),
+            1
+        );
+        assert!(find_similar(&hashes, &[0b1111_1111], None, None, true).is_empty());
+    }
+
+    #[test]
+    fn parses_since() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert_eq!(
+            parse_since("12h", now),
+            Some(now - Duration::from_secs(12 * 60 * 60))
+        );
+        assert_eq!(
+            parse_since("7d", now),
+            Some(now - Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_since("2024-03-01", now),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_251_200))
+        );
+        assert_eq!(parse_since("1970-01-01", now), Some(SystemTime::UNIX_EPOCH));
+        assert_eq!(parse_since("7", now), None);
+        assert_eq!(parse_since("7y", now), None);
+        assert_eq!(parse_since("2024-13-01", now), None);
+    }
+
+    #[test]
+    fn rejects_days_past_the_end_of_the_month() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert_eq!(parse_since("2023-02-31", now), None);
+        assert_eq!(parse_since("2023-04-31", now), None);
+        assert_eq!(parse_since("2023-02-29", now), None);
+        assert_eq!(parse_since("2100-02-29", now), None);
+        assert_eq!(parse_since("2024-01-00", now), None);
+        assert!(parse_since("2024-02-29", now).is_some());
+        assert!(parse_since("2000-02-29", now).is_some());
+        assert!(parse_since("2023-12-31", now).is_some());
+    }
+
+    #[test]
+    fn keeps_highest_resolution() {
+        let mut small_file = stored_image(100, 0);
+        small_file.dimensions = (4000, 3000);
+        let mut large_file = stored_image(200, 0);
+        large_file.dimensions = (2000, 1500);
+        assert_eq!(
+            choose_to_delete(&small_file, &large_file, KeepPolicy::HighestRes),
+            Deletion::Previous
+        );
+        assert_eq!(
+            choose_to_delete(&small_file, &large_file, KeepPolicy::Size),
+            Deletion::New
+        );
+
+        // same resolution falls back to file size
+        large_file.dimensions = (4000, 3000);
+        assert_eq!(
+            choose_to_delete(&small_file, &large_file, KeepPolicy::HighestRes),
+            Deletion::New
+        );
+    }
+
+    #[test]
+    fn keeps_reference_images() {
+        let mut reference = stored_image(100, 0);
+        reference.is_reference = true;
+        let large = stored_image(200, 0);
+        assert_eq!(
+            choose_to_delete(&large, &reference, KeepPolicy::Size),
+            Deletion::New
+        );
+    }
+
+    #[test]
+    fn compares_file_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("a"), b"same bytes").unwrap();
+        fs::write(path("b"), b"same bytes").unwrap();
+        fs::write(path("c"), b"diff bytes").unwrap();
+        fs::write(path("d"), b"same bytes and more").unwrap();
+
+        assert!(same_contents(&path("a"), &path("b")).unwrap());
+        assert!(!same_contents(&path("a"), &path("c")).unwrap());
+        assert!(!same_contents(&path("a"), &path("d")).unwrap());
+        assert!(same_contents(&path("a"), &path("missing")).is_err());
+    }
+
+    #[test]
+    fn checks_keeper_is_readable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("kept"), b"bytes").unwrap();
+
+        assert!(check_readable(&path("kept")).is_ok());
+        assert!(check_readable(&path("missing")).is_err());
+        // a directory can be opened but not read
+        assert!(check_readable(&dir.path().to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn prunes_only_dirs_left_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::create_dir_all(root.join("d")).unwrap();
+        fs::write(root.join("d/.hidden"), "").unwrap();
+        fs::create_dir_all(root.join("already-empty")).unwrap();
+
+        let deleted_from = HashSet::from([root.join("a/b/c"), root.join("d"), root.to_owned()]);
+        assert_eq!(prune_empty_dirs(deleted_from, root), 3);
+        assert!(!root.join("a").exists());
+        assert!(root.join("d").exists());
+        assert!(root.join("already-empty").exists());
+        assert!(root.exists());
+    }
+
+    #[test]
+    fn exact_match_skips_similar_search() {
+        let hashes = BTreeMap::from([
+            (
+                vec![0b0000_0000],
+                vec![StoredImage {
+                    path: "a.png".to_string(),
+                    ..stored_image(1, 0)
+                }],
+            ),
+            (
+                vec![0b0000_0001],
+                vec![StoredImage {
+                    path: "b.png".to_string(),
+                    ..stored_image(1, 0)
+                }],
+            ),
+        ]);
+
+        let (dup_of, sim_to) = find_matches(&hashes, &[0b0000_0000], None, None, true, false);
+        assert_eq!(dup_of.unwrap().1.path, "a.png");
+        assert!(sim_to.is_empty());
+
+        // unless the similar images are going to be listed too
+        let (_, sim_to) = find_matches(&hashes, &[0b0000_0000], None, None, true, true);
+        assert_eq!(sim_to.len(), 1);
+
+        let (dup_of, sim_to) = find_matches(&hashes, &[0b0000_0011], None, None, true, false);
+        assert!(dup_of.is_none());
+        assert_eq!(sim_to.len(), 2);
+    }
+
+    #[test]
+    fn finds_near_misses_past_threshold() {
+        let hashes = BTreeMap::from([
+            (
+                vec![0b0001_1111],
+                vec![StoredImage {
+                    path: "similar.png".to_string(),
+                    ..stored_image(1, 0)
+                }],
+            ),
+            (
+                vec![0b0011_1111],
+                vec![StoredImage {
+                    path: "near.png".to_string(),
+                    ..stored_image(1, 0)
+                }],
+            ),
+            (
+                vec![0b1111_1111],
+                vec![StoredImage {
+                    path: "far.png".to_string(),
+                    ..stored_image(1, 0)
+                }],
+            ),
+        ]);
+
+        let near_misses = find_near_misses(&hashes, &[0], None, None, 2);
+        let near_misses = near_misses
+            .iter()
+            .map(|(image, distance)| (image.path.as_str(), *distance))
+            .collect::<Vec<_>>();
+        assert_eq!(near_misses, [("near.png", 6)]);
+    }
+
+    fn jpeg_of(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([x as u8, y as u8, 128])
+        }));
+        let mut jpeg = Vec::new();
+        image
+            .write_to(&mut io::Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+        jpeg
+    }
+
+    #[test]
+    fn fast_decode_scales_large_jpegs() {
+        let (scaled, dimensions) = decode_jpeg_scaled(&jpeg_of(4000, 80)[..]).unwrap();
+        assert_eq!(dimensions, (4000, 80));
+        assert_eq!((scaled.width(), scaled.height()), (500, 10));
+
+        // 1/4 scale, since 1/8 would make both sides shorter than 256
+        let (scaled, _) = decode_jpeg_scaled(&jpeg_of(1200, 80)[..]).unwrap();
+        assert_eq!((scaled.width(), scaled.height()), (300, 20));
+    }
+
+    #[test]
+    fn flattens_alpha_onto_background() {
+        let image = DynamicImage::ImageRgba8(
+            image::RgbaImage::from_raw(
+                3,
+                1,
+                vec![200, 100, 0, 255, 200, 100, 0, 0, 200, 100, 0, 128],
+            )
+            .unwrap(),
+        );
+        let flattened = flatten_alpha(&image, [0, 0, 255]).to_rgb8();
+        assert_eq!(flattened.into_raw(), [200, 100, 0, 0, 0, 255, 100, 50, 127]);
+        assert_eq!(alpha_channel(&image).to_luma8().into_raw(), [255, 0, 128]);
+
+        // images without transparency are left alone
+        let opaque = DynamicImage::ImageRgb8(image.to_rgb8());
+        assert_eq!(flatten_alpha(&opaque, [0, 0, 255]), opaque);
+        assert_eq!(alpha_channel(&opaque).to_luma8().into_raw(), [255; 3]);
+    }
+
+    #[test]
+    fn formats_similarity_percent() {
+        assert_eq!(similarity_percent(0, 64), "100% similar");
+        assert_eq!(similarity_percent(3, 64), "95% similar");
+        assert_eq!(similarity_percent(32, 64), "50% similar");
+    }
+
+    #[test]
+    fn thumbnails_are_always_deleted() {
+        let original = StoredImage {
+            dimensions: (4000, 3000),
+            ..stored_image(100, 0)
+        };
+        let thumbnail = StoredImage {
+            dimensions: (400, 300),
+            ..stored_image(200, 0)
+        };
+        let resized = StoredImage {
+            dimensions: (3000, 2250),
+            ..stored_image(50, 0)
+        };
+
+        assert!(is_thumbnail_of(&thumbnail, &original));
+        assert!(!is_thumbnail_of(&original, &thumbnail));
+        assert!(!is_thumbnail_of(&resized, &original));
+
+        // even though the thumbnail is the bigger file
+        assert_eq!(
+            choose_to_delete(&thumbnail, &original, KeepPolicy::Size),
+            Deletion::Previous
+        );
+        assert_eq!(
+            thumbnail_deletion(&thumbnail, &original),
+            Some(Deletion::New)
+        );
+        assert_eq!(
+            thumbnail_deletion(&original, &thumbnail),
+            Some(Deletion::Previous)
+        );
+        assert_eq!(thumbnail_deletion(&resized, &original), None);
+
+        let reference_thumbnail = StoredImage {
+            is_reference: true,
+            ..thumbnail
+        };
+        assert_eq!(thumbnail_deletion(&original, &reference_thumbnail), None);
+    }
+
+    #[test]
+    fn resizes_keep_the_higher_resolution() {
+        let original = StoredImage {
+            dimensions: (4000, 3000),
+            ..stored_image(100, 0)
+        };
+        // 4000x3000 scaled to a height of 1000 rounds the width
+        let resized = StoredImage {
+            dimensions: (1333, 1000),
+            ..stored_image(200, 0)
+        };
+        let cropped = StoredImage {
+            dimensions: (3000, 3000),
+            ..stored_image(50, 0)
+        };
+
+        assert!(is_resize_of(&resized, &original));
+        assert!(!is_resize_of(&original, &resized));
+        assert!(!is_resize_of(&cropped, &original));
+        assert!(!is_resize_of(&original, &original));
+
+        // even though the resize is the bigger file
+        assert_eq!(resize_deletion(&resized, &original), Some(Deletion::New));
+        assert_eq!(
+            resize_deletion(&original, &resized),
+            Some(Deletion::Previous)
+        );
+        assert_eq!(resize_deletion(&cropped, &original), None);
+
+        let reference_resize = StoredImage {
+            is_reference: true,
+            ..resized
+        };
+        assert_eq!(resize_deletion(&original, &reference_resize), None);
+    }
+
+    #[test]
+    fn prefers_deleting_renamed_copies() {
+        assert_eq!(original_stem("photo (1)"), Some("photo"));
+        assert_eq!(original_stem("photo (12)"), Some("photo"));
+        assert_eq!(original_stem("photo_copy"), Some("photo"));
+        assert_eq!(original_stem("photo (draft)"), None);
+        assert_eq!(original_stem("photo"), None);
+
+        let original = StoredImage {
+            path: "downloads/photo.jpg".to_string(),
+            ..stored_image(100, 0)
+        };
+        // bigger, so --keep would have kept it
+        let copy = StoredImage {
+            path: "downloads/photo (1).jpg".to_string(),
+            ..stored_image(200, 0)
+        };
+        assert_eq!(filename_deletion(&copy, &original), Some(Deletion::New));
+        assert_eq!(
+            filename_deletion(&original, &copy),
+            Some(Deletion::Previous)
+        );
+        assert_eq!(filename_deletion(&original, &original), None);
+
+        let reference_copy = StoredImage {
+            is_reference: true,
+            ..copy
+        };
+        assert_eq!(filename_deletion(&original, &reference_copy), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn same_device_compares_filesystems() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+        assert!(same_device(a, b).unwrap());
+        // /proc is always its own filesystem
+        if Path::new("/proc/self").exists() {
+            assert!(!same_device(a, "/proc/self").unwrap());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn same_inode_detects_hard_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b, c) = (
+            dir.path().join("a"),
+            dir.path().join("b"),
+            dir.path().join("c"),
+        );
+        fs::write(&a, "a").unwrap();
+        fs::hard_link(&a, &b).unwrap();
+        fs::copy(&a, &c).unwrap();
+        let [a, b, c] = [&a, &b, &c].map(|path| path.to_str().unwrap());
+        assert!(same_inode(a, b));
+        assert!(!same_inode(a, c));
+        assert!(!same_inode(a, "/nonexistent/imdedup/file"));
+    }
+
+    #[test]
+    fn keeps_readonly_images() {
+        let new = StoredImage {
+            path: "scratch/new.png".to_string(),
+            ..stored_image(1, 0)
+        };
+        let previous = StoredImage {
+            path: "master/previous.png".to_string(),
+            ..stored_image(1, 0)
+        };
+        let in_master = |image: &StoredImage| image.path.starts_with("master/");
+
+        assert_eq!(
+            avoid_readonly(Deletion::Previous, &new, &previous, in_master),
+            Some(Deletion::New)
+        );
+        assert_eq!(
+            avoid_readonly(Deletion::New, &new, &previous, in_master),
+            Some(Deletion::New)
+        );
+        assert_eq!(
+            avoid_readonly(Deletion::Previous, &new, &previous, |_| true),
+            None
+        );
+        // the other image is older than --since, so it can't be deleted either
+        let reference = StoredImage {
+            is_reference: true,
+            ..stored_image(1, 0)
+        };
+        assert_eq!(
+            avoid_readonly(Deletion::New, &new, &reference, |image| image
+                .path
+                .starts_with("scratch/")),
+            None
+        );
+    }
+
+    #[test]
+    fn histograms_nearest_distances() {
+        let hashes = [
+            vec![0b0000_0000],
+            vec![0b0000_0000],
+            vec![0b0000_0111],
+            vec![0b1111_1111],
+        ];
+        // the first two are each other's exact match, the third is 3 bits from
+        // them, and the last is 5 bits from the third
+        assert_eq!(
+            nearest_distance_histogram(&hashes, None),
+            [2, 0, 0, 1, 0, 1]
+        );
+        assert!(nearest_distance_histogram(&[vec![0]], None).is_empty());
+    }
+
+    #[test]
+    fn center_crop_keeps_middle() {
+        let image = DynamicImage::new_rgb8(200, 100);
+        let cropped = center_crop(&image, 50);
+        assert_eq!((cropped.width(), cropped.height()), (100, 50));
+
+        let tiny = DynamicImage::new_rgb8(1, 1);
+        let cropped = center_crop(&tiny, 10);
+        assert_eq!((cropped.width(), cropped.height()), (1, 1));
+    }
+
+    #[test]
+    fn deletes_smaller_image() {
+        let small = stored_image(100, 0);
+        let large = stored_image(200, 0);
+        assert_eq!(
+            choose_to_delete(&small, &large, KeepPolicy::Size),
+            Deletion::New
+        );
+        assert_eq!(
+            choose_to_delete(&large, &small, KeepPolicy::Size),
+            Deletion::Previous
+        );
+    }
+
+    #[test]
+    fn deletes_newer_image_when_same_size() {
+        let old = stored_image(100, 10);
+        let new = stored_image(100, 20);
+        assert_eq!(
+            choose_to_delete(&new, &old, KeepPolicy::Size),
+            Deletion::New
+        );
+        assert_eq!(
+            choose_to_delete(&old, &new, KeepPolicy::Size),
+            Deletion::Previous
+        );
+    }
 }
@@ -0,0 +1,56 @@
+//! Keeping track of which stored hashes were matched least recently, so the
+//! oldest can be forgotten once there are more than `--max-index` of them.
+
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Default)]
+pub struct Recency {
+    /// When each hash was last stored or matched.
+    ticks: HashMap<Vec<u8>, u64>,
+    /// The same, ordered from least to most recent.
+    order: BTreeMap<u64, Vec<u8>>,
+    next_tick: u64,
+}
+
+impl Recency {
+    /// Mark a hash as just stored or matched.
+    pub fn touch(&mut self, hash: &[u8]) {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        if let Some(previous_tick) = self.ticks.insert(hash.to_vec(), tick) {
+            self.order.remove(&previous_tick);
+        }
+        self.order.insert(tick, hash.to_vec());
+    }
+
+    pub fn remove(&mut self, hash: &[u8]) {
+        if let Some(tick) = self.ticks.remove(hash) {
+            self.order.remove(&tick);
+        }
+    }
+
+    /// Forget the hash that was matched least recently and return it.
+    pub fn pop_oldest(&mut self) -> Option<Vec<u8>> {
+        let (_, hash) = self.order.pop_first()?;
+        self.ticks.remove(&hash);
+        Some(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_least_recently_touched() {
+        let mut recency = Recency::default();
+        recency.touch(&[1]);
+        recency.touch(&[2]);
+        recency.touch(&[3]);
+        recency.touch(&[1]);
+        recency.remove(&[2]);
+        assert_eq!(recency.pop_oldest(), Some(vec![3]));
+        assert_eq!(recency.pop_oldest(), Some(vec![1]));
+        assert_eq!(recency.pop_oldest(), None);
+    }
+}
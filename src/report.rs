@@ -0,0 +1,159 @@
+//! Comparing the `--manifest`s of two scans of the same collection, for
+//! `--compare-report`.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{error::ImdedupError, hamming_distance, SIMILARITY_THRESHOLD};
+
+/// The path of every file in a manifest, with its hash.
+pub type Manifest = BTreeMap<String, Vec<u8>>;
+
+/// What happened to the duplicates between two scans.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// Files that match another file now but didn't before, with the first
+    /// file they match.
+    pub new_duplicates: Vec<(String, String)>,
+    /// Files that matched another file before, and whether they're gone now
+    /// rather than no longer matching anything.
+    pub resolved_duplicates: Vec<(String, bool)>,
+    /// Files that weren't there before and don't match anything.
+    pub new_uniques: Vec<String>,
+}
+
+/// Read a manifest written by `--manifest`.
+pub fn read_manifest(path: &Path) -> Result<Manifest, ImdedupError> {
+    let contents = fs::read_to_string(path).map_err(|err| ImdedupError::io(path, err))?;
+    let invalid = |line: usize| {
+        ImdedupError::InvalidArgument(format!(
+            "invalid manifest {} on line {}",
+            path.display(),
+            line + 1
+        ))
+    };
+
+    let mut lines = contents.lines().enumerate();
+    if lines.next().map(|(_, line)| line) != Some("hash\tsize\tpath") {
+        return Err(invalid(0));
+    }
+    let mut manifest = Manifest::new();
+    for (i, line) in lines {
+        let [hash, size, file_path] = line.splitn(3, '\t').collect::<Vec<_>>()[..] else {
+            return Err(invalid(i));
+        };
+        size.parse::<u64>().map_err(|_| invalid(i))?;
+        let hash = hex::decode(hash).map_err(|_| invalid(i))?;
+        manifest.insert(unescape_tsv(file_path), hash);
+    }
+    Ok(manifest)
+}
+
+/// Undo the escaping of a path in a tab-separated file.
+fn unescape_tsv(escaped: &str) -> String {
+    let mut path = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('t') => path.push('\t'),
+                Some('n') => path.push('\n'),
+                Some(c) => path.push(c),
+                None => path.push('\\'),
+            }
+        } else {
+            path.push(c);
+        }
+    }
+    path
+}
+
+/// Find every file that matches another file in the manifest, along with the
+/// first of those by path.
+fn duplicates(manifest: &Manifest) -> BTreeMap<&str, &str> {
+    let files = manifest.iter().collect::<Vec<_>>();
+    let mut duplicates = BTreeMap::new();
+    for (i, (path, hash)) in files.iter().enumerate() {
+        let matching = files.iter().enumerate().find(|(j, (_, other_hash))| {
+            i != *j && hamming_distance(hash, other_hash) <= SIMILARITY_THRESHOLD
+        });
+        if let Some((_, (other_path, _))) = matching {
+            duplicates.insert(path.as_str(), other_path.as_str());
+        }
+    }
+    duplicates
+}
+
+/// Work out how the duplicates changed from the old manifest to the new one.
+/// Files are matched the same way a scan matches them, so a file counts as a
+/// duplicate whether it's a dup or sim.
+pub fn diff(old: &Manifest, new: &Manifest) -> Changes {
+    let old_duplicates = duplicates(old);
+    let new_duplicates = duplicates(new);
+    Changes {
+        new_duplicates: new_duplicates
+            .iter()
+            .filter(|(path, _)| !old_duplicates.contains_key(*path))
+            .map(|(path, other_path)| (path.to_string(), other_path.to_string()))
+            .collect(),
+        resolved_duplicates: old_duplicates
+            .keys()
+            .filter(|path| !new_duplicates.contains_key(*path))
+            .map(|path| (path.to_string(), !new.contains_key(*path)))
+            .collect(),
+        new_uniques: new
+            .keys()
+            .filter(|path| !old.contains_key(*path) && !new_duplicates.contains_key(path.as_str()))
+            .cloned()
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(files: &[(&str, u8)]) -> Manifest {
+        files
+            .iter()
+            .map(|&(path, hash)| (path.to_string(), vec![hash; 4]))
+            .collect()
+    }
+
+    #[test]
+    fn reports_changed_duplicates() {
+        let old = manifest(&[
+            ("a.png", 0x00),
+            ("a copy.png", 0x01),
+            ("b.png", 0xf0),
+            ("b copy.png", 0xf0),
+            ("c.png", 0x0f),
+        ]);
+        let new = manifest(&[
+            ("a.png", 0x00),
+            ("a copy.png", 0x01),
+            ("b.png", 0xf0),
+            ("c.png", 0x0f),
+            ("c copy.png", 0x0f),
+            ("d.png", 0xaa),
+        ]);
+        assert_eq!(
+            diff(&old, &new),
+            Changes {
+                new_duplicates: vec![
+                    ("c copy.png".to_string(), "c.png".to_string()),
+                    ("c.png".to_string(), "c copy.png".to_string()),
+                ],
+                resolved_duplicates: vec![
+                    ("b copy.png".to_string(), true),
+                    ("b.png".to_string(), false),
+                ],
+                new_uniques: vec!["d.png".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn unescapes_paths() {
+        assert_eq!(unescape_tsv(r"a\tb\\c\nd"), "a\tb\\c\nd");
+    }
+}
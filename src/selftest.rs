@@ -0,0 +1,183 @@
+//! `imdedup selftest`, which generates an image and some edited copies of it
+//! and shows which of them are detected with the current options.
+
+use std::{
+    env, fs,
+    hash::{BuildHasher, Hasher, RandomState},
+    io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, Rgb, RgbImage};
+
+use crate::{
+    build_hasher, error::ImdedupError, hamming_distance, hash_image, load_image, Args, ExitCode,
+    LoadedImage, Source, SIMILARITY_THRESHOLD,
+};
+
+/// An edited copy of the original image, and whether it should be detected.
+struct Variant {
+    name: &'static str,
+    extension: &'static str,
+    expect_match: bool,
+    write: fn(&RgbImage, &Path) -> Result<(), ImdedupError>,
+}
+
+const VARIANTS: &[Variant] = &[
+    Variant {
+        name: "copy",
+        extension: "png",
+        expect_match: true,
+        write: |original, path| save(&DynamicImage::ImageRgb8(original.clone()), path),
+    },
+    Variant {
+        name: "recompressed",
+        extension: "jpg",
+        expect_match: true,
+        write: |original, path| {
+            let file = fs::File::create(path).map_err(|err| ImdedupError::io(path, err))?;
+            JpegEncoder::new_with_quality(io::BufWriter::new(file), 40)
+                .encode_image(original)
+                .map_err(|err| image_error(path, err))
+        },
+    },
+    Variant {
+        name: "resized",
+        extension: "png",
+        expect_match: true,
+        write: |original, path| {
+            let image = DynamicImage::ImageRgb8(original.clone());
+            save(&image.resize_exact(160, 120, FilterType::Triangle), path)
+        },
+    },
+    // the hashes aren't rotation invariant, so this is only here to show that
+    Variant {
+        name: "rotated",
+        extension: "png",
+        expect_match: false,
+        write: |original, path| save(&DynamicImage::ImageRgb8(original.clone()).rotate90(), path),
+    },
+    Variant {
+        name: "unrelated",
+        extension: "png",
+        expect_match: false,
+        write: |_, path| save(&DynamicImage::ImageRgb8(unrelated_image()), path),
+    },
+];
+
+/// A grid of gradient blocks, which has enough structure to hash meaningfully.
+fn original_image() -> RgbImage {
+    RgbImage::from_fn(320, 240, |x, y| {
+        let block = (x / 80 + y / 60) % 3;
+        Rgb([
+            (x * 255 / 320) as u8,
+            (block * 120) as u8,
+            (y * 255 / 240) as u8,
+        ])
+    })
+}
+
+/// Concentric rings, which look nothing like the original.
+fn unrelated_image() -> RgbImage {
+    RgbImage::from_fn(320, 240, |x, y| {
+        let (dx, dy) = (x as i32 - 160, y as i32 - 120);
+        let ring = ((dx * dx + dy * dy) as f64).sqrt() as u32 / 20;
+        let value = if ring.is_multiple_of(2) { 230 } else { 20 };
+        Rgb([value, 255 - value, value / 2])
+    })
+}
+
+fn save(image: &DynamicImage, path: &Path) -> Result<(), ImdedupError> {
+    image.save(path).map_err(|err| image_error(path, err))
+}
+
+fn image_error(path: &Path, err: image::ImageError) -> ImdedupError {
+    ImdedupError::Decode {
+        path: path.to_string_lossy().to_string(),
+        source: err,
+    }
+}
+
+/// Make a new directory in the temporary directory. Its name has a random
+/// part, and it's only used if it didn't exist yet, so nothing else in a
+/// shared temporary directory can put a directory or symlink there first.
+fn create_temp_dir() -> Result<PathBuf, ImdedupError> {
+    let temp_dir = env::temp_dir();
+    let mut attempts = 0;
+    loop {
+        let suffix = RandomState::new().build_hasher().finish();
+        let dir = temp_dir.join(format!("imdedup-selftest-{}-{suffix:016x}", process::id()));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempts < 10 => {
+                attempts += 1;
+            }
+            Err(err) => return Err(ImdedupError::io(&dir, err)),
+        }
+    }
+}
+
+pub fn run(args: &Args) -> Result<ExitCode, ImdedupError> {
+    let dir = create_temp_dir()?;
+    let result = run_in(args, &dir);
+    // cleaning up is best-effort, the result is what matters
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn run_in(args: &Args, dir: &Path) -> Result<ExitCode, ImdedupError> {
+    let theme = args.theme;
+    let hasher = build_hasher(args);
+    let hash_file = |path: &Path| -> Result<Vec<u8>, ImdedupError> {
+        let Some(LoadedImage { image, .. }) =
+            load_image(&Source::File(path.to_owned()), args, None)?
+        else {
+            return Err(ImdedupError::InvalidArgument(format!(
+                "{} isn't an image",
+                path.display()
+            )));
+        };
+        Ok(hash_image(&hasher, image, args))
+    };
+
+    let original = original_image();
+    let original_path = dir.join("original.png");
+    save(&DynamicImage::ImageRgb8(original.clone()), &original_path)?;
+    let original_hash = hash_file(&original_path)?;
+
+    println!("how edited copies of an image are classified with these options:");
+    let mut all_expected = true;
+    for variant in VARIANTS {
+        let path = dir.join(format!("{}.{}", variant.name, variant.extension));
+        (variant.write)(&original, &path)?;
+        let diff_bits = hamming_distance(&original_hash, &hash_file(&path)?);
+
+        // padded before painting so the color codes don't throw off alignment
+        let label = if diff_bits == 0 {
+            theme.dup.paint("dup ")
+        } else if diff_bits <= SIMILARITY_THRESHOLD {
+            theme.sim.paint("sim ")
+        } else {
+            theme.uniq.paint("uniq")
+        };
+        let matched = diff_bits <= SIMILARITY_THRESHOLD;
+        let outcome = match (matched, variant.expect_match) {
+            (true, true) => "detected",
+            (false, false) => "not detected, as expected",
+            (true, false) => "detected, which is unexpected",
+            (false, true) => "not detected, which is unexpected",
+        };
+        all_expected &= matched == variant.expect_match;
+        println!(
+            "{:<12} {label} {diff_bits:>2} bits  {outcome}",
+            variant.name
+        );
+    }
+
+    Ok(if all_expected {
+        ExitCode::Clean
+    } else {
+        ExitCode::Errors
+    })
+}
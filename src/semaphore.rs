@@ -0,0 +1,70 @@
+//! A counting semaphore, for limiting how many files are read at once with
+//! `--io-threads`.
+
+use parking_lot::{Condvar, Mutex};
+
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Gives the permit back when dropped.
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until a permit is available and take it.
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut available = self.available.lock();
+        while *available == 0 {
+            self.released.wait(&mut available);
+        }
+        *available -= 1;
+        Permit { semaphore: self }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn limits_concurrent_holders() {
+        let semaphore = Semaphore::new(2);
+        let holders = AtomicUsize::new(0);
+        let max_holders = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _permit = semaphore.acquire();
+                    let current = holders.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_holders.fetch_max(current, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    holders.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(max_holders.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,49 @@
+//! Sending the scan's progress and summary to the system log, for `--syslog`.
+
+use std::time::Duration;
+
+#[cfg(unix)]
+use parking_lot::Mutex;
+#[cfg(unix)]
+use syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+
+use crate::error::ImdedupError;
+
+/// How often progress is logged. It's much less often than `--progress-file`
+/// is updated, so a long run doesn't flood the log.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct SystemLog {
+    #[cfg(unix)]
+    logger: Mutex<Logger<LoggerBackend, Formatter3164>>,
+}
+
+impl SystemLog {
+    #[cfg(unix)]
+    pub fn connect() -> Result<Self, ImdedupError> {
+        let formatter = Formatter3164 {
+            facility: Facility::LOG_USER,
+            hostname: None,
+            process: "imdedup".to_string(),
+            pid: std::process::id(),
+        };
+        let logger =
+            syslog::unix(formatter).map_err(|err| ImdedupError::Syslog(err.to_string()))?;
+        Ok(Self {
+            logger: Mutex::new(logger),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect() -> Result<Self, ImdedupError> {
+        Err(ImdedupError::Syslog("only Unix has one".to_string()))
+    }
+
+    pub fn info(&self, message: &str) {
+        // the scan shouldn't fail because the log went away
+        #[cfg(unix)]
+        let _ = self.logger.lock().info(message);
+        #[cfg(not(unix))]
+        let _ = message;
+    }
+}
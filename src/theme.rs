@@ -0,0 +1,100 @@
+//! The colors the output is painted with, for `--theme` and `--no-color`.
+
+use std::fmt;
+
+/// An ANSI SGR color, like `91` for bright red. An empty color means no color.
+#[derive(Clone, Copy)]
+pub struct Color(&'static str);
+
+impl Color {
+    pub fn paint<T: fmt::Display>(self, text: T) -> Painted<T> {
+        Painted { color: self, text }
+    }
+}
+
+pub struct Painted<T> {
+    color: Color,
+    text: T,
+}
+
+impl<T: fmt::Display> fmt::Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.color.0.is_empty() {
+            write!(f, "{}", self.text)
+        } else {
+            write!(f, "\x1b[{}m{}\x1b[m", self.color.0, self.text)
+        }
+    }
+}
+
+/// The colors used for each kind of output.
+pub struct Theme {
+    pub dup: Color,
+    pub sim: Color,
+    pub near: Color,
+    pub uniq: Color,
+    pub blk: Color,
+    pub err: Color,
+    pub warning: Color,
+    pub hash: Color,
+}
+
+impl Theme {
+    pub const DEFAULT: Theme = Theme {
+        dup: Color("91"),
+        sim: Color("93"),
+        near: Color("33"),
+        uniq: Color("96"),
+        blk: Color("95"),
+        err: Color("31"),
+        warning: Color("93"),
+        hash: Color("90"),
+    };
+
+    /// Bold text on solid backgrounds, for terminals where the bright colors
+    /// are hard to read.
+    pub const HIGH_CONTRAST: Theme = Theme {
+        dup: Color("1;97;41"),
+        sim: Color("1;30;103"),
+        near: Color("1;30;43"),
+        uniq: Color("1;30;106"),
+        blk: Color("1;97;45"),
+        err: Color("1;97;41"),
+        warning: Color("1;93"),
+        hash: Color("37"),
+    };
+
+    /// Orange and blue instead of red, yellow, and green-ish colors, which are
+    /// easier to tell apart with the common kinds of color blindness.
+    pub const COLORBLIND: Theme = Theme {
+        dup: Color("38;5;208"),
+        sim: Color("38;5;33"),
+        near: Color("38;5;110"),
+        uniq: Color("38;5;255"),
+        blk: Color("38;5;162"),
+        err: Color("1;4;38;5;208"),
+        warning: Color("1;38;5;33"),
+        hash: Color("90"),
+    };
+
+    pub const NONE: Theme = Theme {
+        dup: Color(""),
+        sim: Color(""),
+        near: Color(""),
+        uniq: Color(""),
+        blk: Color(""),
+        err: Color(""),
+        warning: Color(""),
+        hash: Color(""),
+    };
+
+    pub fn from_name(name: &str) -> Option<&'static Theme> {
+        match name {
+            "default" => Some(&Self::DEFAULT),
+            "high-contrast" => Some(&Self::HIGH_CONTRAST),
+            "colorblind" => Some(&Self::COLORBLIND),
+            "none" => Some(&Self::NONE),
+            _ => None,
+        }
+    }
+}
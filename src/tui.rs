@@ -0,0 +1,277 @@
+//! Reviewing groups of matching images one at a time and choosing which to
+//! delete, for `--tui`.
+
+use std::{collections::HashMap, io};
+
+use image::{imageops::FilterType, DynamicImage};
+use ratatui::{
+    buffer::Buffer,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, List, ListItem, ListState, Paragraph, Widget},
+    DefaultTerminal, Frame,
+};
+
+use crate::error::ImdedupError;
+
+/// Previews are decoded at this size at most and kept for as long as the
+/// review lasts, so going back to a group doesn't decode its images again.
+const PREVIEW_SIZE: u32 = 256;
+
+/// An image in one of the groups being reviewed.
+#[derive(Clone)]
+pub struct File {
+    pub path: String,
+    pub hash: Vec<u8>,
+    pub file_size: u64,
+    pub dimensions: (u32, u32),
+    /// False for images that are never deleted, like ones from urls, ones
+    /// older than `--since`, and ones in `--readonly` directories.
+    pub deletable: bool,
+}
+
+/// Show each group of matching images in the terminal and let the files to
+/// delete be picked, returning each one with a file its group keeps once that's
+/// confirmed, or nothing if the review is quit instead.
+pub fn review(groups: Vec<Vec<File>>) -> Result<Vec<(File, File)>, ImdedupError> {
+    if groups.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut terminal = ratatui::try_init().map_err(ImdedupError::Terminal)?;
+    let result = Review::new(groups).run(&mut terminal);
+    ratatui::try_restore().map_err(ImdedupError::Terminal)?;
+    result.map_err(ImdedupError::Terminal)
+}
+
+struct Review {
+    groups: Vec<Vec<File>>,
+    /// Which files of each group are marked to be deleted.
+    marked: Vec<Vec<bool>>,
+    group: usize,
+    selected: usize,
+    /// Decoded previews by path, or `None` for images that can't be previewed.
+    previews: HashMap<String, Option<DynamicImage>>,
+    status: String,
+    confirming: bool,
+}
+
+impl Review {
+    fn new(groups: Vec<Vec<File>>) -> Self {
+        Self {
+            marked: groups
+                .iter()
+                .map(|group| vec![false; group.len()])
+                .collect(),
+            groups,
+            group: 0,
+            selected: 0,
+            previews: HashMap::new(),
+            status: String::new(),
+            confirming: false,
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> io::Result<Vec<(File, File)>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if self.confirming {
+                if key.code == KeyCode::Char('y') {
+                    return Ok(self.deletions());
+                }
+                self.confirming = false;
+                self.status.clear();
+                continue;
+            }
+
+            self.status.clear();
+            let group_len = self.groups[self.group].len();
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(Vec::new()),
+                KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+                KeyCode::Down => self.selected = (self.selected + 1).min(group_len - 1),
+                KeyCode::Right | KeyCode::Char('n') => self.move_to_group(self.group + 1),
+                KeyCode::Left | KeyCode::Char('p') if self.group > 0 => {
+                    self.move_to_group(self.group - 1)
+                }
+                KeyCode::Char('d') => self.mark(true),
+                KeyCode::Char('k') => self.mark(false),
+                KeyCode::Enter => {
+                    let count = self.deletions().len();
+                    if count == 0 {
+                        self.status = "no files are marked to be deleted".to_string();
+                    } else {
+                        self.status = format!("delete {count} files? (y/n)");
+                        self.confirming = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn move_to_group(&mut self, group: usize) {
+        if group < self.groups.len() {
+            self.group = group;
+            self.selected = 0;
+        }
+    }
+
+    fn mark(&mut self, delete: bool) {
+        let file = &self.groups[self.group][self.selected];
+        let marked = &mut self.marked[self.group];
+        if delete && !file.deletable {
+            self.status = format!("{} can't be deleted", file.path);
+        } else if delete && marked.iter().filter(|&&marked| !marked).count() == 1 {
+            // otherwise there'd be no copy left
+            self.status = "at least one file of each group has to be kept".to_string();
+        } else {
+            marked[self.selected] = delete;
+        }
+    }
+
+    /// The marked files, each with the first file of its group that's kept, so
+    /// it's deleted with the same checks as the worse copy of a pair.
+    fn deletions(&self) -> Vec<(File, File)> {
+        let mut deletions = Vec::new();
+        for (group, marked) in self.groups.iter().zip(&self.marked) {
+            let Some(kept) = group.iter().zip(marked).find(|(_, &marked)| !marked) else {
+                continue;
+            };
+            for (file, _) in group.iter().zip(marked).filter(|(_, &marked)| marked) {
+                deletions.push((file.clone(), kept.0.clone()));
+            }
+        }
+        deletions
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header_area, body_area, footer_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(body_area);
+
+        let group = &self.groups[self.group];
+        let marked = &self.marked[self.group];
+        frame.render_widget(
+            Paragraph::new(format!(
+                "group {}/{}, {} files, {} marked to be deleted in total",
+                self.group + 1,
+                self.groups.len(),
+                group.len(),
+                self.deletions().len()
+            )),
+            header_area,
+        );
+
+        let items = group.iter().zip(marked).map(|(file, &marked)| {
+            let (label, style) = if marked {
+                ("del ", Style::new().fg(Color::Red))
+            } else if file.deletable {
+                ("keep", Style::new())
+            } else {
+                ("kept", Style::new().fg(Color::DarkGray))
+            };
+            ListItem::new(format!(
+                "{label} {} ({}x{}, {} bytes)",
+                file.path, file.dimensions.0, file.dimensions.1, file.file_size
+            ))
+            .style(style)
+        });
+        let list = List::new(items)
+            .block(Block::bordered())
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut list_state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(list, list_area, &mut list_state);
+
+        let file = &group[self.selected];
+        let preview = self.previews.entry(file.path.clone()).or_insert_with(|| {
+            image::open(&file.path)
+                .ok()
+                .map(|image| image.thumbnail(PREVIEW_SIZE, PREVIEW_SIZE))
+        });
+        let block = Block::bordered().title(file.path.as_str());
+        let inner_area = block.inner(preview_area);
+        frame.render_widget(block, preview_area);
+        match preview {
+            Some(image) => frame.render_widget(Preview(image), inner_area),
+            None => frame.render_widget(Paragraph::new("no preview"), inner_area),
+        }
+
+        frame.render_widget(
+            Paragraph::new(format!(
+                "up/down select  d delete  k keep  left/right group  enter apply  q quit\n{}",
+                self.status
+            )),
+            footer_area,
+        );
+    }
+}
+
+/// An image drawn with half blocks, so every character shows two pixels, one
+/// above the other.
+struct Preview<'a>(&'a DynamicImage);
+
+impl Widget for Preview<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let image = self
+            .0
+            .resize(
+                area.width as u32,
+                area.height as u32 * 2,
+                FilterType::Triangle,
+            )
+            .to_rgb8();
+        for y in 0..image.height().div_ceil(2) {
+            for x in 0..image.width() {
+                let top = image.get_pixel(x, y * 2);
+                let bottom = image.get_pixel(x, (y * 2 + 1).min(image.height() - 1));
+                if let Some(cell) = buf.cell_mut((area.x + x as u16, area.y + y as u16)) {
+                    cell.set_char('▀')
+                        .set_fg(Color::Rgb(top[0], top[1], top[2]))
+                        .set_bg(Color::Rgb(bottom[0], bottom[1], bottom[2]));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str) -> File {
+        File {
+            path: path.to_string(),
+            hash: Vec::new(),
+            file_size: 0,
+            dimensions: (0, 0),
+            deletable: true,
+        }
+    }
+
+    #[test]
+    fn keeps_one_file_of_each_group() {
+        let mut review = Review::new(vec![vec![file("a.png"), file("b.png")]]);
+        review.mark(true);
+        review.selected = 1;
+        review.mark(true);
+        let deletions = review
+            .deletions()
+            .into_iter()
+            .map(|(file, kept)| (file.path, kept.path))
+            .collect::<Vec<_>>();
+        assert_eq!(deletions, [("a.png".to_string(), "b.png".to_string())]);
+    }
+}
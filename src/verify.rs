@@ -0,0 +1,79 @@
+//! Re-scanning the directory after deleting, for `--verify`.
+
+use std::path::{Path, PathBuf};
+
+use image_hasher::Hasher;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    animation, collect_input, error::ImdedupError, exact, hash_image, load_image, match_distance,
+    weights, Args, LoadedImage, Source, SIMILARITY_THRESHOLD,
+};
+
+pub struct Report {
+    /// How many images were hashed again.
+    pub checked: usize,
+    /// Pairs of remaining images that still match, and how many bits differ.
+    pub leftovers: Vec<(PathBuf, PathBuf, u32)>,
+    /// Images that can't be read or decoded anymore.
+    pub broken: Vec<ImdedupError>,
+}
+
+/// Hash every image in the directory again, finding the ones that still match
+/// each other and the ones that can't be opened anymore.
+pub fn verify(args: &Args, hasher: &Hasher, dir: &Path) -> Result<Report, ImdedupError> {
+    let mut paths = Vec::new();
+    collect_input(dir, args, &mut paths)?;
+    paths.sort();
+
+    let results = paths
+        .par_iter()
+        .map(|path| {
+            let source = Source::File(path.clone());
+            if let Some(mode) = args.exact {
+                let hashed = exact::hash(&source, mode, args, None)?;
+                return Ok(hashed.map(|hashed| (path, hashed.hash, None)));
+            }
+            let loaded = load_image(&source, args, None)?;
+            Ok(loaded.map(|LoadedImage { image, frames, .. }| {
+                let signature = animation::signature(hasher, frames, args);
+                (path, hash_image(hasher, image, args), signature)
+            }))
+        })
+        .collect::<Vec<Result<_, ImdedupError>>>();
+
+    let mut hashes = Vec::new();
+    let mut broken = Vec::new();
+    for result in results {
+        match result {
+            Ok(Some(hashed)) => hashes.push(hashed),
+            Ok(None) => {}
+            Err(err) => broken.push(err),
+        }
+    }
+
+    let weights = args
+        .weighted_distance
+        .then(|| weights::bit_weights(hashes.iter().map(|(_, hash, _)| hash.as_slice())));
+    let mut leftovers = Vec::new();
+    for (i, (a_path, a_hash, a_signature)) in hashes.iter().enumerate() {
+        for (b_path, b_hash, b_signature) in &hashes[i + 1..] {
+            let diff_bits = match_distance(
+                a_hash,
+                a_signature.as_deref(),
+                b_hash,
+                b_signature.as_deref(),
+                weights.as_deref(),
+            );
+            if diff_bits <= SIMILARITY_THRESHOLD {
+                leftovers.push((a_path.to_path_buf(), b_path.to_path_buf(), diff_bits));
+            }
+        }
+    }
+
+    Ok(Report {
+        checked: hashes.len(),
+        leftovers,
+        broken,
+    })
+}
@@ -0,0 +1,84 @@
+//! Weighting hash bits by how much they vary across the scanned images, for
+//! `--weighted-distance`.
+
+/// Work out how much each bit of the hashes counts towards the distance
+/// between two of them. A bit's weight is proportional to its variance across
+/// all the hashes, so bits that are the same in almost every image (like ones
+/// covering a flat background that every image in the set shares) count for
+/// little, and bits that split the images evenly count the most. The weights
+/// add up to the number of bits, so weighted distances are on the same scale
+/// as plain ones and `SIMILARITY_THRESHOLD` still applies.
+pub fn bit_weights<'a>(hashes: impl IntoIterator<Item = &'a [u8]>) -> Vec<f64> {
+    let mut ones = Vec::<u64>::new();
+    let mut count = 0;
+    for hash in hashes {
+        if ones.is_empty() {
+            ones = vec![0; hash.len() * 8];
+        }
+        if hash.len() * 8 != ones.len() {
+            continue;
+        }
+        for (bit, ones) in ones.iter_mut().enumerate() {
+            *ones += u64::from(hash[bit / 8] >> (bit % 8) & 1);
+        }
+        count += 1;
+    }
+
+    let variances = ones
+        .iter()
+        .map(|&ones| {
+            let p = ones as f64 / count as f64;
+            p * (1. - p)
+        })
+        .collect::<Vec<_>>();
+    let total_variance = variances.iter().sum::<f64>();
+    // every image has the same hash, so there's nothing to learn from
+    if total_variance == 0. {
+        return vec![1.; variances.len()];
+    }
+    variances
+        .iter()
+        .map(|variance| variance * variances.len() as f64 / total_variance)
+        .collect()
+}
+
+/// Add up the weights of the bits that differ between two hashes, rounded to
+/// the nearest whole bit. Hashes that the weights weren't computed for are
+/// compared by plain hamming distance.
+pub fn weighted_distance(a: &[u8], b: &[u8], weights: &[f64]) -> u32 {
+    if a.len() != b.len() || a.len() * 8 != weights.len() {
+        return crate::hamming_distance(a, b);
+    }
+    let total = weights
+        .iter()
+        .enumerate()
+        .filter(|&(bit, _)| (a[bit / 8] ^ b[bit / 8]) >> (bit % 8) & 1 == 1)
+        .map(|(_, weight)| weight)
+        .sum::<f64>();
+    total.round() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_bits_count_for_nothing() {
+        // the top four bits are set in every hash, the bottom four vary
+        let hashes = [[0xf0], [0xf3], [0xfc], [0xff]];
+        let weights = bit_weights(hashes.iter().map(|hash| hash.as_slice()));
+        assert_eq!(weights, [2., 2., 2., 2., 0., 0., 0., 0.]);
+
+        assert_eq!(weighted_distance(&[0xf0], &[0xf3], &weights), 4);
+        // the bits that never vary across the set count for nothing
+        assert_eq!(weighted_distance(&[0xf0], &[0x00], &weights), 0);
+        assert_eq!(weighted_distance(&[0xf0, 0], &[0x00, 0], &weights), 4);
+    }
+
+    #[test]
+    fn identical_hashes_are_weighted_evenly() {
+        let hashes = [[0x5a], [0x5a]];
+        let weights = bit_weights(hashes.iter().map(|hash| hash.as_slice()));
+        assert_eq!(weights, [1.; 8]);
+    }
+}
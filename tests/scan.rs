@@ -0,0 +1,990 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn copy_fixture(name: &str, to: &Path) {
+    fs::create_dir_all(to.parent().unwrap()).unwrap();
+    fs::copy(fixtures_dir().join(name), to).unwrap();
+}
+
+/// Run imdedup with these environment variables set, and without any
+/// `IMDEDUP_OPTS` from the one running the tests unless it's one of them.
+/// Whether it succeeded is left to the caller.
+fn run_with_env(args: &[&str], vars: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_imdedup"))
+        .args(args)
+        .env_remove("IMDEDUP_OPTS")
+        .envs(vars.iter().copied())
+        .output()
+        .unwrap()
+}
+
+/// Run imdedup and return its stdout split into lines, with the color codes
+/// removed.
+fn run(args: &[&str]) -> Vec<String> {
+    let output = run_with_env(args, &[]);
+    assert!(output.status.success(), "{output:?}");
+
+    let mut stdout = String::from_utf8(output.stdout).unwrap();
+    while let Some(start) = stdout.find('\x1b') {
+        let end = start + stdout[start..].find('m').unwrap();
+        stdout.replace_range(start..=end, "");
+    }
+    stdout
+        .split(['\n', '\r'])
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Parse the final "N dup, N sim, N uniq" line.
+fn summary(lines: &[String]) -> (u64, u64, u64) {
+    let counts = lines
+        .last()
+        .unwrap()
+        .split(", ")
+        .map(|part| part.split(' ').next().unwrap().parse().unwrap())
+        .collect::<Vec<u64>>();
+    (counts[0], counts[1], counts[2])
+}
+
+#[test]
+fn classifies_fixtures() {
+    let fixtures = fixtures_dir();
+    let lines = run(&[fixtures.to_str().unwrap()]);
+    // reading files separately from decoding them doesn't change anything
+    let limited_lines = run(&[fixtures.to_str().unwrap(), "--io-threads", "1"]);
+    assert_eq!(summary(&limited_lines), summary(&lines));
+    // and the copies are clear-cut enough that weighting the bits doesn't
+    // either
+    let weighted_lines = run(&[fixtures.to_str().unwrap(), "--weighted-distance"]);
+    assert_eq!(summary(&weighted_lines), summary(&lines));
+
+    let (dup, sim, uniq) = summary(&lines);
+    // the copy, recompressed, and resized images all match the original
+    assert_eq!(dup + sim, 3);
+    assert_eq!(uniq, 2);
+
+    for line in &lines {
+        if line.contains(" dup ") || line.contains(" sim ") {
+            assert!(!line.contains("unrelated.png"), "{line}");
+        }
+    }
+}
+
+#[test]
+fn delete_keeps_largest_copy() {
+    let dir = tempfile::tempdir().unwrap();
+    for entry in fs::read_dir(fixtures_dir()).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), dir.path().join(entry.file_name())).unwrap();
+    }
+
+    let lines = run(&[
+        dir.path().to_str().unwrap(),
+        "--delete",
+        "--yes",
+        "--verify",
+    ]);
+    assert_eq!(
+        lines.last().unwrap(),
+        "verified 2 images: 0 matching pairs left, 0 can't be opened"
+    );
+
+    let mut remaining = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    remaining.sort();
+    assert_eq!(remaining, ["recompressed.jpg", "unrelated.png"]);
+}
+
+#[test]
+fn recursive_skips_junk_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_fixture("original.png", &dir.path().join("original.png"));
+    copy_fixture("copy.png", &dir.path().join("album/copy.png"));
+    copy_fixture("copy.png", &dir.path().join("node_modules/copy.png"));
+    copy_fixture("copy.png", &dir.path().join(".cache/copy.png"));
+    let dir = dir.path().to_str().unwrap();
+
+    assert_eq!(summary(&run(&[dir])), (0, 0, 1));
+    assert_eq!(summary(&run(&[dir, "--recursive"])), (1, 0, 1));
+    assert_eq!(
+        summary(&run(&[dir, "--recursive", "--skip-dir", "album"])),
+        (0, 0, 1)
+    );
+    assert_eq!(
+        summary(&run(&[dir, "--recursive", "--descend-all"])),
+        (3, 0, 1)
+    );
+}
+
+#[test]
+fn delete_never_touches_readonly_dirs() {
+    let dir = tempfile::tempdir().unwrap();
+    let master = dir.path().join("master");
+    let scratch = dir.path().join("scratch");
+    copy_fixture("original.png", &master.join("original.png"));
+    copy_fixture("recompressed.jpg", &master.join("recompressed.jpg"));
+    // the larger copy would normally be the one kept
+    copy_fixture("copy.png", &scratch.join("copy.png"));
+    copy_fixture("resized.png", &scratch.join("resized.png"));
+
+    run(&[
+        dir.path().to_str().unwrap(),
+        "--recursive",
+        "--delete",
+        "--yes",
+        "--readonly",
+        master.to_str().unwrap(),
+    ]);
+
+    assert!(master.join("original.png").exists());
+    assert!(master.join("recompressed.jpg").exists());
+    assert_eq!(fs::read_dir(&scratch).unwrap().count(), 0);
+}
+
+#[test]
+fn blocklist_deletions_respect_readonly_dirs() {
+    let dir = tempfile::tempdir().unwrap();
+    let master = dir.path().join("master");
+    let scratch = dir.path().join("scratch");
+    copy_fixture("original.png", &master.join("original.png"));
+    copy_fixture("copy.png", &scratch.join("copy.png"));
+    let blocklist = dir.path().join("blocklist.txt");
+    // the hash of original.png
+    fs::write(&blocklist, "5affff7ebdffffbd\n").unwrap();
+
+    let output = run_with_env(
+        &[
+            dir.path().to_str().unwrap(),
+            "--recursive",
+            "--delete",
+            "--yes",
+            "--readonly",
+            master.to_str().unwrap(),
+            "--blocklist",
+            blocklist.to_str().unwrap(),
+            "--relative-to",
+            dir.path().to_str().unwrap(),
+        ],
+        &[("NO_COLOR", "1")],
+    );
+    // blocklisted images fail the scan whether or not they're deleted
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+
+    assert!(master.join("original.png").exists());
+    assert!(!scratch.join("copy.png").exists());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("del scratch/copy.png"), "{stdout}");
+    assert!(!stdout.contains("del master"), "{stdout}");
+}
+
+#[test]
+fn checkpoint_skips_processed_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("images");
+    copy_fixture("original.png", &images.join("original.png"));
+    copy_fixture("unrelated.png", &images.join("unrelated.png"));
+    let checkpoint = dir.path().join("checkpoint");
+    let args = [
+        images.to_str().unwrap(),
+        "--checkpoint",
+        checkpoint.to_str().unwrap(),
+    ];
+
+    assert_eq!(summary(&run(&args)), (0, 0, 2));
+
+    copy_fixture("copy.png", &images.join("copy.png"));
+    let lines = run(&args);
+    assert!(lines[0].starts_with("resuming from checkpoint, 2 files"));
+    // only the new file is scanned, but the counts include the earlier run
+    assert_eq!(lines.len(), 3);
+    assert_eq!(summary(&lines), (1, 0, 2));
+}
+
+#[test]
+fn checkpoint_rejects_different_hash_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = dir.path().join("checkpoint");
+    let fixtures = fixtures_dir();
+    let args = [
+        fixtures.to_str().unwrap(),
+        "--checkpoint",
+        checkpoint.to_str().unwrap(),
+    ];
+    run(&args);
+
+    let output = run_with_env(&[args.as_slice(), &["--dct"]].concat(), &[]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("different hashing options"), "{stderr}");
+
+    // the same options can still resume from it
+    run(&args);
+}
+
+#[test]
+fn exit_code_reflects_outcome() {
+    let exit_code = |args: &[&str]| run_with_env(args, &[]).status.code();
+    let fixtures = fixtures_dir();
+    let fixtures = fixtures.to_str().unwrap();
+    assert_eq!(exit_code(&[fixtures]), Some(0));
+    assert_eq!(exit_code(&[fixtures, "--fail-on-dup"]), Some(1));
+    assert_eq!(exit_code(&[fixtures, "--no-such-option"]), Some(2));
+
+    let dir = tempfile::tempdir().unwrap();
+    copy_fixture("original.png", &dir.path().join("original.png"));
+    fs::write(
+        dir.path().join("broken.png"),
+        b"\x89PNG\r\n\x1a\nnot really",
+    )
+    .unwrap();
+    assert_eq!(exit_code(&[dir.path().to_str().unwrap()]), Some(3));
+    assert_eq!(exit_code(&["/nonexistent/imdedup/dir"]), Some(3));
+}
+
+#[test]
+fn scans_single_file() {
+    let original = fixtures_dir().join("original.png");
+    let lines = run(&[original.to_str().unwrap()]);
+    assert_eq!(summary(&lines), (0, 0, 1));
+
+    let output = run_with_env(&["/nonexistent/imdedup/image.png", "--no-color"], &[]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("/nonexistent/imdedup/image.png doesn't exist"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn uniques_only_lists_unmatched_images() {
+    let fixtures = fixtures_dir();
+    let lines = run(&[fixtures.to_str().unwrap(), "--uniques-only"]);
+    let unrelated = fixtures.join("unrelated.png");
+    assert_eq!(lines, [unrelated.to_str().unwrap()]);
+}
+
+#[test]
+fn min_group_size_lists_large_groups() {
+    let fixtures = fixtures_dir();
+    let fixtures = fixtures.to_str().unwrap();
+    let lines = run(&[fixtures, "--min-group-size", "4", "--relative-to", fixtures]);
+    let start = lines
+        .iter()
+        .position(|line| line.starts_with("group"))
+        .unwrap();
+    assert_eq!(
+        lines[start..start + 5],
+        [
+            "group of 4 images:",
+            "copy.png",
+            "original.png",
+            "recompressed.jpg",
+            "resized.png"
+        ]
+    );
+
+    // smaller groups aren't listed, but they're still counted
+    let lines = run(&[fixtures, "--min-group-size", "5"]);
+    assert!(
+        !lines.iter().any(|line| line.starts_with("group")),
+        "{lines:?}"
+    );
+    assert_eq!(summary(&lines), summary(&run(&[fixtures])));
+}
+
+#[test]
+fn count_only_prints_just_the_counts() {
+    let fixtures = fixtures_dir();
+    let fixtures = fixtures.to_str().unwrap();
+    let output = run_with_env(&[fixtures, "--count-only"], &[]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    let (dup, sim, uniq) = summary(&run(&[fixtures]));
+    assert_eq!(stdout, format!("{dup} {sim} {uniq}\n"));
+}
+
+#[test]
+fn rejects_conflicting_options() {
+    let fixtures = fixtures_dir();
+    let fixtures = fixtures.to_str().unwrap();
+    let cases: &[(&[&str], &str)] = &[
+        (
+            &["--delete", "--uniques-only"],
+            "--delete can't be combined with --uniques-only",
+        ),
+        (
+            &["--delete", "--histogram"],
+            "--histogram is read-only and can't be combined with --delete",
+        ),
+        (
+            &["--uniques-only", "--near-miss", "3"],
+            "--near-miss can't be combined with --uniques-only",
+        ),
+        (
+            &["--uniques-only", "--percent"],
+            "--percent can't be combined with --uniques-only",
+        ),
+        (
+            &["--count-only", "--delete"],
+            "--delete can't be combined with --count-only",
+        ),
+        (
+            &["--weighted-distance", "--checkpoint", "checkpoint.txt"],
+            "--weighted-distance learns from every image in one run",
+        ),
+        (
+            &["--tui", "--delete"],
+            "--tui needs every match from one scan to choose what to delete itself, so it can't be combined with --delete",
+        ),
+        (
+            &["--exact", "bytes", "--near-miss", "3"],
+            "--near-miss only applies to perceptual hashes, so it can't be combined with --exact",
+        ),
+        (
+            &["--readonly", "."],
+            "--readonly only applies with --delete",
+        ),
+        (
+            &["--duplicate-dirs"],
+            "--duplicate-dirs only applies with --recursive",
+        ),
+        (
+            &["--delete-suspicious"],
+            "--delete-suspicious only applies with --delete",
+        ),
+        (&["--verify"], "--verify only applies with --delete"),
+        (
+            &["--verify-bytes"],
+            "--verify-bytes only applies with --delete",
+        ),
+        (&["--quiet"], "--quiet only applies with --syslog"),
+        (
+            &["--alpha-background", "000000"],
+            "--alpha-background only applies with --alpha-mode flatten or separate",
+        ),
+        (
+            &["--max-index", "10", "--uniques-only"],
+            "--uniques-only can't be combined with --max-index",
+        ),
+        (
+            &["--syslog", "--quiet", "--histogram"],
+            "--histogram can't be combined with --quiet",
+        ),
+        (
+            &["--same-device-only"],
+            "--same-device-only only applies with --delete",
+        ),
+        (
+            &["--keep", "highest-res"],
+            "--keep only applies with --delete or --copy-uniques-to",
+        ),
+        (
+            &["--skip-dir", "cache"],
+            "--skip-dir only applies with --recursive",
+        ),
+        (
+            &["--min-group-size", "3", "--uniques-only"],
+            "--min-group-size can't be combined with --uniques-only",
+        ),
+        (
+            &["--descend-all"],
+            "--descend-all only applies with --recursive",
+        ),
+    ];
+
+    for (args, expected_error) in cases {
+        let output = run_with_env(&[&[fixtures], *args, &["--no-color"]].concat(), &[]);
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(expected_error), "{args:?}: {stderr}");
+    }
+}
+
+#[test]
+fn scans_zip_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive_path = dir.path().join("images.zip");
+    let mut zip = zip::ZipWriter::new(fs::File::create(&archive_path).unwrap());
+    for entry in fs::read_dir(fixtures_dir()).unwrap() {
+        let entry = entry.unwrap();
+        let name = format!("set/{}", entry.file_name().into_string().unwrap());
+        zip.start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&fs::read(entry.path()).unwrap()).unwrap();
+    }
+    zip.finish().unwrap();
+    let archive_path = archive_path.to_str().unwrap();
+
+    let lines = run(&["--archive", archive_path]);
+    let (dup, sim, uniq) = summary(&lines);
+    assert_eq!(dup + sim, 3);
+    assert_eq!(uniq, 2);
+    let copy = format!("{archive_path}:set/copy.png");
+    assert!(lines.iter().any(|line| line.contains(&copy)), "{lines:?}");
+
+    let output = run_with_env(&["--archive", archive_path, "--delete"], &[]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn progress_file_reports_completion() {
+    let dir = tempfile::tempdir().unwrap();
+    let progress_path = dir.path().join("progress");
+    run(&[
+        fixtures_dir().to_str().unwrap(),
+        "--progress-file",
+        progress_path.to_str().unwrap(),
+    ]);
+    assert_eq!(fs::read_to_string(&progress_path).unwrap(), "5/5 100.0%\n");
+    assert!(!dir.path().join("progress.tmp").exists());
+}
+
+#[test]
+fn selftest_detects_edited_copies() {
+    let lines = run(&["selftest"]);
+    assert_eq!(lines.len(), 6, "{lines:?}");
+    for line in &lines[1..] {
+        assert!(!line.contains("unexpected"), "{line}");
+    }
+    assert!(lines[1].starts_with("copy") && lines[1].contains("dup"));
+}
+
+#[test]
+fn pairs_lists_every_match() {
+    let dir = tempfile::tempdir().unwrap();
+    let pairs_path = dir.path().join("pairs.tsv");
+    run(&[
+        fixtures_dir().to_str().unwrap(),
+        "--pairs",
+        pairs_path.to_str().unwrap(),
+    ]);
+
+    let pairs = fs::read_to_string(&pairs_path).unwrap();
+    let mut lines = pairs.lines();
+    assert_eq!(lines.next(), Some("a\tb\tdistance\tstatus"));
+    let pairs = lines
+        .map(|line| line.split('\t').collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert!(pairs.len() >= 3, "{pairs:?}");
+    for pair in &pairs {
+        let [a, b, distance, status] = pair[..] else {
+            panic!("{pair:?}");
+        };
+        assert!(!a.ends_with("unrelated.png") && !b.ends_with("unrelated.png"));
+        let distance = distance.parse::<u32>().unwrap();
+        assert_eq!(status, if distance == 0 { "dup" } else { "sim" });
+    }
+}
+
+#[test]
+fn keeps_unicode_file_names_intact() {
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("фото");
+    copy_fixture("original.png", &images.join("café.png"));
+    copy_fixture("copy.png", &images.join("写真 (コピー).png"));
+    let pairs = dir.path().join("pairs.tsv");
+    let manifest = dir.path().join("manifest.tsv");
+
+    let lines = run(&[
+        images.to_str().unwrap(),
+        "--pairs",
+        pairs.to_str().unwrap(),
+        "--manifest",
+        manifest.to_str().unwrap(),
+        "--relative-to",
+        images.to_str().unwrap(),
+    ]);
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("写真 (コピー).png") && line.contains("café.png")),
+        "{lines:?}"
+    );
+
+    // both are written as UTF-8, whatever the console uses
+    let pairs = fs::read_to_string(&pairs).unwrap();
+    assert!(pairs.contains("写真 (コピー).png") && pairs.contains("café.png"));
+    let manifest = fs::read_to_string(&manifest).unwrap();
+    assert!(manifest.contains(images.join("café.png").to_str().unwrap()));
+}
+
+#[test]
+fn compares_two_images() {
+    let fixtures = fixtures_dir();
+    let fixture = |name: &str| fixtures.join(name).to_str().unwrap().to_string();
+    let compare = |a: &str, b: &str| {
+        run_with_env(&["--compare", &fixture(a), &fixture(b), "--no-color"], &[])
+    };
+
+    let output = compare("original.png", "recompressed.jpg");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("sim "));
+
+    let output = compare("original.png", "unrelated.png");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("different "));
+}
+
+#[test]
+fn delete_skips_blank_images() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a.png", "b.png", "c.png"] {
+        image::RgbImage::from_pixel(64, 64, image::Rgb([255, 255, 255]))
+            .save(dir.path().join(name))
+            .unwrap();
+    }
+
+    let lines = run(&[dir.path().to_str().unwrap(), "--delete", "--yes"]);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    assert!(lines.iter().any(|line| line.ends_with("matched 3 images")));
+    assert_eq!(summary(&lines), (2, 0, 1));
+
+    run(&[
+        dir.path().to_str().unwrap(),
+        "--delete",
+        "--delete-suspicious",
+        "--yes",
+    ]);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn finds_duplicate_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    for tree in ["photos", "backup/photos"] {
+        copy_fixture("original.png", &dir.path().join(tree).join("a.png"));
+        copy_fixture("unrelated.png", &dir.path().join(tree).join("b/c.png"));
+    }
+    copy_fixture("original.png", &dir.path().join("partial/a.png"));
+    copy_fixture("resized.png", &dir.path().join("partial/d.png"));
+
+    let lines = run(&[dir.path().to_str().unwrap(), "-r", "--duplicate-dirs"]);
+    let report = lines
+        .iter()
+        .skip_while(|line| !line.starts_with("duplicate directories:"))
+        .collect::<Vec<_>>();
+    let root = dir.path().display();
+    assert_eq!(
+        report,
+        [
+            "duplicate directories: 1",
+            &format!("{root}/backup/photos duplicates {root}/photos"),
+        ]
+    );
+}
+
+#[test]
+fn relative_to_shortens_paths() {
+    let fixtures = fixtures_dir();
+    let lines = run(&[
+        fixtures.to_str().unwrap(),
+        "--relative-to",
+        fixtures.parent().unwrap().to_str().unwrap(),
+    ]);
+
+    let matches = lines
+        .iter()
+        .filter(|line| line.contains(" dup ") || line.contains(" sim "))
+        .collect::<Vec<_>>();
+    assert_eq!(matches.len(), 3);
+    for line in matches {
+        let paths = line
+            .split_once(" dup ")
+            .or(line.split_once(" sim "))
+            .unwrap()
+            .1;
+        assert!(paths.starts_with("fixtures/"), "{line}");
+        assert!(!paths.contains(fixtures.to_str().unwrap()), "{line}");
+    }
+}
+
+/// Write an animated GIF made of the fixtures in order.
+#[cfg(feature = "gif")]
+fn write_gif(path: &Path, frame_names: &[&str]) {
+    let file = fs::File::create(path).unwrap();
+    let mut encoder = image::codecs::gif::GifEncoder::new(file);
+    for name in frame_names {
+        let frame = image::open(fixtures_dir().join(name))
+            .unwrap()
+            .resize_exact(64, 48, image::imageops::FilterType::Triangle)
+            .to_rgba8();
+        encoder.encode_frame(image::Frame::new(frame)).unwrap();
+    }
+}
+
+#[cfg(feature = "gif")]
+#[test]
+fn animated_signature_compares_later_frames() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.gif");
+    let b = dir.path().join("b.gif");
+    write_gif(&a, &["original.png"; 8]);
+    write_gif(&b, &[["original.png"; 4], ["unrelated.png"; 4]].concat());
+    let compare = |extra_args: &[&str]| {
+        let args = ["--compare", a.to_str().unwrap(), b.to_str().unwrap()];
+        run_with_env(&[args.as_slice(), extra_args].concat(), &[])
+            .status
+            .code()
+    };
+
+    // only the first frames are the same
+    assert_eq!(compare(&[]), Some(0));
+    assert_eq!(compare(&["--animated-signature"]), Some(1));
+}
+
+#[cfg(feature = "gif")]
+#[test]
+fn animations_sharing_a_first_frame_are_both_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    write_gif(&dir.path().join("a.gif"), &["original.png"; 8]);
+    write_gif(
+        &dir.path().join("b.gif"),
+        &[["original.png"; 4], ["unrelated.png"; 4]].concat(),
+    );
+    write_gif(&dir.path().join("c.gif"), &["original.png"; 8]);
+    let dir = dir.path().to_str().unwrap();
+
+    // b.gif is compared before c.gif, and mustn't take a.gif's place
+    let lines = run(&[
+        dir,
+        "--animated-signature",
+        "--order-by",
+        "path",
+        "--relative-to",
+        dir,
+    ]);
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.ends_with("dup c.gif == a.gif")),
+        "{lines:?}"
+    );
+    assert_eq!(summary(&lines), (1, 0, 2));
+}
+
+#[test]
+fn exact_only_matches_identical_images() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in [
+        "original.png",
+        "copy.png",
+        "resized.png",
+        "recompressed.jpg",
+    ] {
+        copy_fixture(name, &dir.path().join(name));
+    }
+    // the same pixels as the original, stored as 16-bit RGBA
+    let converted = image::open(fixtures_dir().join("original.png"))
+        .unwrap()
+        .to_rgba16();
+    converted.save(dir.path().join("converted.png")).unwrap();
+    let dir = dir.path().to_str().unwrap();
+
+    // the resized and recompressed images only match perceptually
+    assert_eq!(summary(&run(&[dir, "--exact", "pixels"])), (2, 0, 3));
+    assert_eq!(summary(&run(&[dir, "--exact", "bytes"])), (1, 0, 4));
+}
+
+#[test]
+fn alpha_mode_controls_transparent_matches() {
+    let dir = tempfile::tempdir().unwrap();
+    let original = image::open(fixtures_dir().join("original.png"))
+        .unwrap()
+        .to_rgba8();
+    let width = original.width();
+    // the left half is see-through in one copy and white in the other
+    let mut transparent = original.clone();
+    let mut on_white = original;
+    for (x, _, pixel) in transparent.enumerate_pixels_mut() {
+        if x < width / 2 {
+            *pixel = image::Rgba([0, 0, 0, 0]);
+        }
+    }
+    for (x, _, pixel) in on_white.enumerate_pixels_mut() {
+        if x < width / 2 {
+            *pixel = image::Rgba([255, 255, 255, 255]);
+        }
+    }
+    transparent
+        .save(dir.path().join("transparent.png"))
+        .unwrap();
+    on_white.save(dir.path().join("on_white.png")).unwrap();
+    let dir = dir.path().to_str().unwrap();
+
+    assert_eq!(summary(&run(&[dir])), (0, 0, 2));
+    assert_eq!(summary(&run(&[dir, "--alpha-mode", "flatten"])), (1, 0, 1));
+    let on_black = ["--alpha-mode", "flatten", "--alpha-background", "000000"];
+    assert_eq!(summary(&run(&[&[dir][..], &on_black].concat())), (0, 0, 2));
+    assert_eq!(summary(&run(&[dir, "--alpha-mode", "separate"])), (0, 0, 2));
+}
+
+#[test]
+fn detect_thumbnails_deletes_smaller_copy() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_fixture("original.png", &dir.path().join("original.png"));
+    copy_fixture("resized.png", &dir.path().join("resized.png"));
+
+    let lines = run(&[dir.path().to_str().unwrap(), "--detect-thumbnails"]);
+    assert!(lines.iter().any(|line| line.contains(" thumb ")
+        && line.ends_with(&format!(
+            "resized.png thumbnail of {}",
+            dir.path().join("original.png").display()
+        ))));
+
+    run(&[
+        dir.path().to_str().unwrap(),
+        "--detect-thumbnails",
+        "--delete",
+        "--yes",
+    ]);
+    assert!(dir.path().join("original.png").exists());
+    assert!(!dir.path().join("resized.png").exists());
+}
+
+#[test]
+fn per_dir_never_matches_across_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_fixture("original.png", &dir.path().join("album1/original.png"));
+    copy_fixture("copy.png", &dir.path().join("album1/copy.png"));
+    copy_fixture("copy.png", &dir.path().join("album2/copy.png"));
+    copy_fixture(
+        "unrelated.png",
+        &dir.path().join("album2/nested/unrelated.png"),
+    );
+    let dir = dir.path().to_str().unwrap();
+
+    let lines = run(&[dir, "-r", "--per-dir", "--relative-to", dir]);
+    let album2 = lines.iter().position(|line| line == "album2:").unwrap();
+    assert_eq!(lines[0], "album1:");
+    assert_eq!(summary(&lines[..album2]), (1, 0, 1));
+    assert_eq!(summary(&lines[album2..]), (0, 0, 2));
+}
+
+#[test]
+fn manifest_lists_files_left_after_deleting() {
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("images");
+    for entry in fs::read_dir(fixtures_dir()).unwrap() {
+        let entry = entry.unwrap();
+        copy_fixture(
+            entry.file_name().to_str().unwrap(),
+            &images.join(entry.file_name()),
+        );
+    }
+    let manifest = dir.path().join("manifest.tsv");
+
+    run(&[
+        images.to_str().unwrap(),
+        "--delete",
+        "--yes",
+        "--manifest",
+        manifest.to_str().unwrap(),
+    ]);
+
+    let contents = fs::read_to_string(&manifest).unwrap();
+    let mut lines = contents.lines();
+    assert_eq!(lines.next(), Some("hash\tsize\tpath"));
+    let files = lines
+        .map(|line| {
+            let [hash, size, path] = line.split('\t').collect::<Vec<_>>()[..] else {
+                panic!("{line}");
+            };
+            assert_eq!(hash.len(), 16);
+            assert_eq!(
+                size.parse::<u64>().unwrap(),
+                fs::metadata(path).unwrap().len()
+            );
+            path.to_string()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        files,
+        [
+            images.join("recompressed.jpg").to_str().unwrap(),
+            images.join("unrelated.png").to_str().unwrap()
+        ]
+    );
+}
+
+#[test]
+fn reads_default_options_from_environment() {
+    let fixtures = fixtures_dir();
+    let run_with = |opts: &str, args: &[&str]| {
+        let args = [&[fixtures.to_str().unwrap()], args].concat();
+        run_with_env(&args, &[("IMDEDUP_OPTS", opts)])
+    };
+
+    let output = run_with("--fail-on-dup --theme none", &[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!output.stdout.contains(&b'\x1b'));
+
+    // the command line comes after, so it overrides the environment
+    let output = run_with("--theme none", &["--theme", "high-contrast"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.contains(&b'\x1b'));
+
+    assert_eq!(run_with("'unterminated", &[]).status.code(), Some(2));
+}
+
+#[test]
+fn compare_report_shows_changed_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("images");
+    copy_fixture("original.png", &images.join("original.png"));
+    copy_fixture("copy.png", &images.join("copy.png"));
+    let [old, new] = ["old.tsv", "new.tsv"].map(|name| dir.path().join(name));
+    let scan = |manifest: &Path| {
+        run(&[
+            images.to_str().unwrap(),
+            "--manifest",
+            manifest.to_str().unwrap(),
+        ])
+    };
+
+    scan(&old);
+    fs::remove_file(images.join("copy.png")).unwrap();
+    copy_fixture("unrelated.png", &images.join("unrelated.png"));
+    copy_fixture("recompressed.jpg", &images.join("recompressed.jpg"));
+    scan(&new);
+
+    let lines = run(&[
+        "--compare-report",
+        old.to_str().unwrap(),
+        new.to_str().unwrap(),
+        "--relative-to",
+        images.to_str().unwrap(),
+    ]);
+    assert_eq!(
+        lines,
+        [
+            "new dup recompressed.jpg ~= original.png",
+            "resolved copy.png (gone)",
+            "new uniq unrelated.png",
+            "1 new duplicates, 1 resolved, 1 new unique files",
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn closed_stdout_stops_without_panicking() {
+    use std::{os::unix::process::ExitStatusExt, process::Stdio};
+
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..50 {
+        copy_fixture("original.png", &dir.path().join(format!("{i}.png")));
+    }
+    let mut child = Command::new(env!("CARGO_BIN_EXE_imdedup"))
+        .arg(dir.path())
+        .env_remove("IMDEDUP_OPTS")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // like `imdedup dir | head -0`
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success() || output.status.signal() == Some(libc::SIGPIPE),
+        "{output:?}"
+    );
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("panicked"));
+}
+
+/// A 1x1 QOI image, which isn't a format that's decoded by default.
+#[cfg(not(feature = "qoi"))]
+const QOI_PIXEL: &[u8] =
+    b"qoif\x00\x00\x00\x01\x00\x00\x00\x01\x04\x00\xff\x80\x80\x80\xff\x00\x00\x00\x00\x00\x00\x00\x01";
+
+#[cfg(not(feature = "qoi"))]
+#[test]
+fn reports_formats_left_out_of_the_build() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_fixture("original.png", &dir.path().join("original.png"));
+    fs::write(dir.path().join("pixel.qoi"), QOI_PIXEL).unwrap();
+
+    let output = run_with_env(&[dir.path().to_str().unwrap(), "--no-color"], &[]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("built without the `qoi` feature"),
+        "{stdout}"
+    );
+}
+
+/// Serve the body as a file with the name over HTTP on localhost, to a single
+/// request, and return its url.
+#[cfg(all(feature = "url", not(feature = "qoi")))]
+fn serve_once(name: &str, body: &'static [u8]) -> String {
+    use std::io::Read;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/{name}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0; 4096];
+        let _ = stream.read(&mut request);
+        let _ = write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        let _ = stream.write_all(body);
+    });
+    url
+}
+
+#[cfg(all(feature = "url", not(feature = "qoi")))]
+#[test]
+fn reports_urls_in_formats_left_out_of_the_build() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_fixture("original.png", &dir.path().join("original.png"));
+    let url_list = dir.path().join("urls.txt");
+    fs::write(&url_list, serve_once("pixel.qoi", QOI_PIXEL)).unwrap();
+
+    let output = run_with_env(
+        &[
+            dir.path().join("original.png").to_str().unwrap(),
+            "--url-list",
+            url_list.to_str().unwrap(),
+            "--no-color",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("built without the `qoi` feature"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn order_by_gives_the_same_output_every_time() {
+    let fixtures = fixtures_dir();
+    let fixtures = fixtures.to_str().unwrap();
+    let args = [fixtures, "--order-by", "path", "--relative-to", fixtures];
+    let lines = run(&args);
+    for _ in 0..3 {
+        assert_eq!(run(&args), lines);
+    }
+    // the first path of the group is the one the rest are matched against
+    assert!(lines.contains(&"2/5 5affff7ebdffffbd dup original.png == copy.png".to_string()));
+    assert_eq!(summary(&lines), summary(&run(&[fixtures])));
+}